@@ -0,0 +1,234 @@
+//! Benchmarks for map loading and the per-cell lookups done while rendering
+//!
+//! The `nested` benchmarks use a copy of the loader from before the flat
+//! storage: it parses the same file into a `Vec` of rows, each cell holding
+//! its own copy of its tileset name. Both loaders read the file from memory,
+//! so the load benchmarks measure parsing and storage rather than disk access.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use op2mapviewerrust::map::format::MapFormat;
+use op2mapviewerrust::map::loader::NativeFormat;
+use op2mapviewerrust::map::{CellType, Diagnostics, Map, Position};
+
+const SAMPLE_MAP: &str = "on6_01.map";
+
+/// Size of a terrain type record in the map file
+const TERRAIN_TYPE_SIZE: usize = 264;
+
+/// Tile words are stored in vertical strips this many columns wide
+const TILE_COLUMN_BLOCK: u32 = 32;
+
+/// Tile information as the nested layout stored it, with an owned tileset name
+#[derive(Debug, Clone)]
+struct NestedTileInfo {
+    tileset_name: String,
+    tile_index: u32,
+}
+
+/// A cell as the nested layout stored it, with its tile information inline
+#[derive(Debug, Clone)]
+struct NestedCell {
+    position: Position,
+    cell_type: CellType,
+    height: u8,
+    has_wreckage: bool,
+    has_unit: bool,
+    tile_info: Option<NestedTileInfo>,
+}
+
+impl NestedCell {
+    fn new(position: Position, cell_type: CellType, height: u8) -> Self {
+        Self {
+            position,
+            cell_type,
+            height,
+            has_wreckage: false,
+            has_unit: false,
+            tile_info: None,
+        }
+    }
+}
+
+/// A map in the nested layout
+struct NestedMap {
+    tileset_sources: Vec<String>,
+    cells: Vec<Vec<NestedCell>>,
+}
+
+/// Reads little-endian values from the file contents
+struct ByteReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn bytes(&mut self, len: usize) -> &'a [u8] {
+        let bytes = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        bytes
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.bytes(2).try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.bytes(4).try_into().unwrap())
+    }
+
+    fn sized_string(&mut self) -> String {
+        let len = self.u32() as usize;
+        String::from_utf8_lossy(self.bytes(len)).into_owned()
+    }
+}
+
+/// The loader from before the flat storage, reduced to the native layout
+fn load_nested(data: &[u8]) -> NestedMap {
+    let mut reader = ByteReader { data, offset: 0 };
+    let _version_tag = reader.u32();
+    let _is_saved_game = reader.u32();
+    let width = 1u32 << reader.u32();
+    let height = reader.u32();
+    let tileset_count = reader.u32();
+
+    let tiles: Vec<u32> = (0..width * height).map(|_| reader.u32()).collect();
+    let _clip_rect = reader.bytes(16);
+
+    let tileset_sources: Vec<String> = (0..tileset_count)
+        .map(|_| {
+            let name = reader.sized_string();
+            if !name.is_empty() {
+                let _num_tiles = reader.u32();
+            }
+            name
+        })
+        .collect();
+    assert_eq!(reader.bytes(10), b"TILE SET\x1a\0");
+
+    let mapping_count = reader.u32();
+    let tile_mappings: Vec<[u16; 4]> = (0..mapping_count)
+        .map(|_| [reader.u16(), reader.u16(), reader.u16(), reader.u16()])
+        .collect();
+    let terrain_count = reader.u32() as usize;
+    let _terrain_types = reader.bytes(terrain_count * TERRAIN_TYPE_SIZE);
+
+    let _version_tags = reader.bytes(8);
+    let group_count = reader.u32();
+    let _unknown = reader.u32();
+    for _ in 0..group_count {
+        let group_width = reader.u32() as usize;
+        let group_height = reader.u32() as usize;
+        let _mapping_indices = reader.bytes(group_width * group_height * 4);
+        let _name = reader.sized_string();
+    }
+
+    let mut cells =
+        vec![
+            vec![NestedCell::new(Position::new(0, 0), CellType::default(), 0); width as usize];
+            height as usize
+        ];
+    for y in 0..height {
+        for x in 0..width {
+            let block = x / TILE_COLUMN_BLOCK;
+            let column = x % TILE_COLUMN_BLOCK;
+            let word = tiles
+                [(block * height * TILE_COLUMN_BLOCK + y * TILE_COLUMN_BLOCK + column) as usize];
+
+            let cell_type = CellType::from_index((word & 0x1F) as u8).unwrap_or_default();
+            let mut cell = NestedCell::new(Position::new(x as i32, y as i32), cell_type, 0);
+            cell.has_unit = (word >> 16) & 0x7FF != 0;
+            cell.tile_info = tile_mappings.get(((word >> 5) & 0x7FF) as usize).and_then(
+                |&[tileset_index, tile_index, _, _]| {
+                    let name = tileset_sources.get(tileset_index as usize)?;
+                    (!name.is_empty()).then(|| NestedTileInfo {
+                        tileset_name: name.clone(),
+                        tile_index: tile_index as u32,
+                    })
+                },
+            );
+            cells[y as usize][x as usize] = cell;
+        }
+    }
+
+    NestedMap {
+        tileset_sources,
+        cells,
+    }
+}
+
+fn load_flat(data: &[u8]) -> Map {
+    NativeFormat
+        .load(data, &mut Diagnostics::new())
+        .expect("sample map should load")
+}
+
+fn read_sample() -> Vec<u8> {
+    std::fs::read(Path::new(SAMPLE_MAP)).expect("sample map should be readable")
+}
+
+fn bench_load(c: &mut Criterion) {
+    let data = read_sample();
+    c.bench_function("load/flat", |b| b.iter(|| load_flat(black_box(&data))));
+    c.bench_function("load/nested", |b| b.iter(|| load_nested(black_box(&data))));
+}
+
+/// Stands in for the renderer's texture lookup: a table indexed by tileset
+/// slot for the flat layout, and a map keyed by tileset name for the nested one
+fn bench_render_iteration(c: &mut Criterion) {
+    let data = read_sample();
+
+    let map = load_flat(&data);
+    let slot_textures: Vec<Option<u32>> = (0..map.tileset_sources.len() as u16)
+        .map(|slot| map.tileset_name(slot).map(|_| slot as u32))
+        .collect();
+    c.bench_function("render_iteration/flat", |b| {
+        b.iter(|| {
+            let mut sum = 0u64;
+            for y in 0..map.info.height as i32 {
+                for x in 0..map.info.width as i32 {
+                    let cell = map.get_cell(x, y).unwrap();
+                    let texture = map.tile_info(cell.tile).and_then(|info| {
+                        let texture = slot_textures[info.tileset_index as usize]?;
+                        Some(texture as u64 + info.tile_index as u64)
+                    });
+                    sum += texture.unwrap_or(cell.cell_type().index() as u64);
+                    sum += cell.height as u64 + cell.has_wreckage as u64 + cell.has_unit as u64;
+                    sum += cell.position.x as u64;
+                }
+            }
+            black_box(sum)
+        })
+    });
+
+    let nested = load_nested(&data);
+    let named_textures: HashMap<String, u32> = nested
+        .tileset_sources
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| !name.is_empty())
+        .map(|(slot, name)| (name.clone(), slot as u32))
+        .collect();
+    c.bench_function("render_iteration/nested", |b| {
+        b.iter(|| {
+            let mut sum = 0u64;
+            for row in &nested.cells {
+                for cell in row {
+                    let texture = cell.tile_info.as_ref().and_then(|info| {
+                        let texture = named_textures.get(&info.tileset_name)?;
+                        Some(*texture as u64 + info.tile_index as u64)
+                    });
+                    sum += texture.unwrap_or(cell.cell_type.index() as u64);
+                    sum += cell.height as u64 + cell.has_wreckage as u64 + cell.has_unit as u64;
+                    sum += cell.position.x as u64;
+                }
+            }
+            black_box(sum)
+        })
+    });
+}
+
+criterion_group!(benches, bench_load, bench_render_iteration);
+criterion_main!(benches);
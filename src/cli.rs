@@ -0,0 +1,425 @@
+//! Command-line tools for OP2MapViewer
+//!
+//! Running the binary with one of these commands as its first argument runs
+//! the command instead of opening the viewer window.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use image::RgbaImage;
+use thiserror::Error;
+
+use op2mapviewerrust::map::{load_map, load_tilesets, Diagnostics, Map, MapSummary, Severity};
+use op2mapviewerrust::render::{render_map, save_image, Overlay, RenderOptions};
+use op2mapviewerrust::ui::image_export::MAX_IMAGE_PIXELS;
+
+const USAGE: &str = "\
+Usage:
+  op2mapviewer [map]                                Open the map viewer
+  op2mapviewer render <map> -o <out.png> [options]  Render a map to an image
+  op2mapviewer info <map> [--json]                  Print statistics about a map
+
+Render options:
+  --tilesets <zip|vol|dir>  Tilesets to draw the map with
+  -o, --output <file>       Image to write (PNG or JPEG)
+  --scale <n>               Output pixels per tile pixel (default 1)
+  --grid                    Draw cell borders
+  --overlay <name>          Tint cells by an overlay; may be repeated. Names:
+                            celltype, passability, lava-possible, microbe,
+                            wall, units, expand";
+
+/// Largest image the render command writes, in pixels: as many as the
+/// viewer puts in one exported file
+const MAX_RENDER_PIXELS: u64 = MAX_IMAGE_PIXELS as u64 * MAX_IMAGE_PIXELS as u64;
+
+/// First arguments that select a command instead of the viewer
+const COMMANDS: &[&str] = &["render", "info", "help", "--help", "-h"];
+
+/// Returns true if `arg` names one of the commands
+pub fn is_command(arg: &str) -> bool {
+    COMMANDS.contains(&arg)
+}
+
+/// Error type for command-line tools
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("{0}\n\n{USAGE}")]
+    Usage(String),
+
+    #[error(transparent)]
+    MapLoadError(#[from] op2mapviewerrust::map::MapLoadError),
+
+    #[error("Could not write image: {0}")]
+    ImageError(#[from] image::ImageError),
+
+    #[error("IO error: {0}")]
+    IoError(#[from] io::Error),
+}
+
+/// Runs the command named by the first argument and returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let result = match args.first().map(String::as_str) {
+        Some("render") => render_command(&args[1..]),
+        Some("info") => info_command(&args[1..]),
+        Some("help" | "--help" | "-h") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(other) => Err(CliError::Usage(format!("Unknown command: {}", other))),
+        None => Err(CliError::Usage("No command given".into())),
+    };
+
+    match result {
+        Ok(()) => 0,
+        // Output piped into `head` and the like stops being read early
+        Err(CliError::IoError(e)) if e.kind() == io::ErrorKind::BrokenPipe => 0,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+/// Arguments of the `render` command
+struct RenderArgs {
+    map: PathBuf,
+    tilesets: Option<PathBuf>,
+    output: PathBuf,
+    options: RenderOptions,
+}
+
+fn parse_render_args(args: &[String]) -> Result<RenderArgs, CliError> {
+    let mut map = None;
+    let mut tilesets = None;
+    let mut output = None;
+    let mut options = RenderOptions::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| CliError::Usage(format!("{} needs a value", name)))
+        };
+        match arg.as_str() {
+            "--tilesets" => tilesets = Some(PathBuf::from(value(arg)?)),
+            "-o" | "--output" => output = Some(PathBuf::from(value(arg)?)),
+            "--scale" => {
+                let scale = value(arg)?;
+                options.scale = scale
+                    .parse()
+                    .ok()
+                    .filter(|scale: &f32| *scale > 0.0 && scale.is_finite())
+                    .ok_or_else(|| CliError::Usage(format!("Invalid scale: {}", scale)))?;
+            }
+            "--grid" => options.grid = true,
+            "--overlay" => {
+                let overlay = value(arg)?.parse::<Overlay>().map_err(CliError::Usage)?;
+                if !options.overlays.contains(&overlay) {
+                    options.overlays.push(overlay);
+                }
+            }
+            flag if flag.starts_with('-') => {
+                return Err(CliError::Usage(format!("Unknown option: {}", flag)))
+            }
+            path if map.is_none() => map = Some(PathBuf::from(path)),
+            extra => return Err(CliError::Usage(format!("Unexpected argument: {}", extra))),
+        }
+    }
+
+    Ok(RenderArgs {
+        map: map.ok_or_else(|| CliError::Usage("No map given".into()))?,
+        tilesets,
+        output: output.ok_or_else(|| CliError::Usage("No output file given".into()))?,
+        options,
+    })
+}
+
+fn render_command(args: &[String]) -> Result<(), CliError> {
+    let args = parse_render_args(args)?;
+    let mut map = load_map_reporting(&args.map)?;
+
+    if let Some(path) = &args.tilesets {
+        let mut diagnostics = Diagnostics::new();
+        let cache = load_tilesets(path, &mut diagnostics);
+        report(&diagnostics);
+        map.set_tileset_cache(cache?);
+    }
+
+    let image = render(&map, &args.options)?;
+    save_image(&image, &args.output)?;
+    eprintln!(
+        "Wrote {}x{} image to {}",
+        image.width(),
+        image.height(),
+        args.output.display()
+    );
+    Ok(())
+}
+
+/// Renders a map, refusing scales that would make the image too large to allocate
+fn render(map: &Map, options: &RenderOptions) -> Result<RgbaImage, CliError> {
+    checked_output_size(map, options)?;
+    Ok(render_map(map, options))
+}
+
+/// Size of the rendered image, if it is within [`MAX_RENDER_PIXELS`]
+fn checked_output_size(map: &Map, options: &RenderOptions) -> Result<(u32, u32), CliError> {
+    options
+        .output_size(map)
+        .filter(|&(width, height)| width as u64 * height as u64 <= MAX_RENDER_PIXELS)
+        .ok_or_else(|| {
+            CliError::Usage(format!(
+                "Scale {} is too large: a {}x{} map would be over {} pixels",
+                options.scale, map.info.width, map.info.height, MAX_RENDER_PIXELS
+            ))
+        })
+}
+
+fn info_command(args: &[String]) -> Result<(), CliError> {
+    let mut map_path = None;
+    let mut json = false;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            flag if flag.starts_with('-') => {
+                return Err(CliError::Usage(format!("Unknown option: {}", flag)))
+            }
+            path if map_path.is_none() => map_path = Some(PathBuf::from(path)),
+            extra => return Err(CliError::Usage(format!("Unexpected argument: {}", extra))),
+        }
+    }
+    let map_path = map_path.ok_or_else(|| CliError::Usage("No map given".into()))?;
+
+    let map = load_map_reporting(&map_path)?;
+    let summary = MapSummary::new(&map);
+    let mut out = io::stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut out, &summary).map_err(io::Error::from)?;
+        writeln!(out)?;
+    } else {
+        write_summary(&mut out, &summary)?;
+    }
+    Ok(())
+}
+
+fn write_summary(out: &mut impl Write, summary: &MapSummary) -> io::Result<()> {
+    let info = &summary.info;
+    writeln!(out, "Name:          {}", info.name)?;
+    writeln!(out, "Size:          {}x{}", info.width, info.height)?;
+    if let Some(format) = summary.format {
+        writeln!(out, "Format:        {}", format)?;
+    }
+    if !info.description.is_empty() {
+        writeln!(out, "Description:   {}", info.description)?;
+    }
+    if !info.author.is_empty() {
+        writeln!(out, "Author:        {}", info.author)?;
+    }
+    if !info.requirements.is_empty() {
+        writeln!(out, "Requirements:  {}", info.requirements.join(", "))?;
+    }
+    writeln!(out, "Version tag:   {:#x}", info.version_tag)?;
+    if info.is_saved_game {
+        writeln!(out, "Saved game:    {} units", summary.unit_count)?;
+    }
+    writeln!(out, "Tile mappings: {}", summary.tile_mapping_count)?;
+
+    writeln!(out, "\nTilesets ({}):", summary.tilesets.len())?;
+    for tileset in &summary.tilesets {
+        writeln!(
+            out,
+            "  {:>3}  {:<12} {} tiles",
+            tileset.slot, tileset.name, tileset.num_tiles
+        )?;
+    }
+
+    writeln!(out, "\nTerrain types ({}):", summary.terrain_types.len())?;
+    for (index, terrain) in summary.terrain_types.iter().enumerate() {
+        writeln!(
+            out,
+            "  {:>3}  mappings {}-{}",
+            index, terrain.first_mapping, terrain.last_mapping
+        )?;
+    }
+
+    writeln!(out, "\nTile groups ({}):", summary.tile_groups.len())?;
+    for group in &summary.tile_groups {
+        writeln!(out, "  {:<24} {}x{}", group.name, group.width, group.height)?;
+    }
+
+    writeln!(out, "\nCell types:")?;
+    for entry in &summary.cell_types {
+        writeln!(out, "  {:<18} {:>7}", entry.cell_type.name(), entry.count)?;
+    }
+    Ok(())
+}
+
+/// Loads a map, printing any problems the loader reported
+fn load_map_reporting(path: &Path) -> Result<Map, CliError> {
+    let mut diagnostics = Diagnostics::new();
+    let map = load_map(path, &mut diagnostics);
+    report(&diagnostics);
+    Ok(map?)
+}
+
+/// Prints warnings and errors from a loader to stderr
+fn report(diagnostics: &Diagnostics) {
+    for entry in diagnostics.entries() {
+        if entry.severity >= Severity::Warning {
+            eprintln!("{}", entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use op2mapviewerrust::map::{CellType, MapInfo, TileWord};
+    use op2mapviewerrust::render::cell_type_rgb;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn usage_error(result: Result<RenderArgs, CliError>) -> String {
+        match result {
+            Err(CliError::Usage(message)) => message,
+            Err(other) => panic!("expected a usage error, got {}", other),
+            Ok(_) => panic!("expected a usage error"),
+        }
+    }
+
+    /// A map whose cells alternate between two cell types
+    fn checkered_map(width: u32, height: u32) -> Map {
+        let mut map = Map::new(MapInfo {
+            width,
+            height,
+            ..Default::default()
+        });
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                let cell_type = if (x + y) % 2 == 0 {
+                    CellType::FastPassible1
+                } else {
+                    CellType::Impassible1
+                };
+                *map.tile_mut(x, y).unwrap() = TileWord::new(cell_type.index(), 0);
+            }
+        }
+        map
+    }
+
+    #[test]
+    fn parses_render_arguments() {
+        let parsed = parse_render_args(&args(&[
+            "on6_01.map",
+            "--tilesets",
+            "art.vol",
+            "-o",
+            "out.png",
+            "--scale",
+            "0.5",
+            "--grid",
+            "--overlay",
+            "units",
+            "--overlay",
+            "Microbe",
+            "--overlay",
+            "units",
+        ]))
+        .unwrap();
+        assert_eq!(parsed.map, PathBuf::from("on6_01.map"));
+        assert_eq!(parsed.tilesets, Some(PathBuf::from("art.vol")));
+        assert_eq!(parsed.output, PathBuf::from("out.png"));
+        assert_eq!(parsed.options.scale, 0.5);
+        assert!(parsed.options.grid);
+        assert_eq!(parsed.options.overlays, [Overlay::Units, Overlay::Microbe]);
+    }
+
+    #[test]
+    fn usage_lists_every_overlay() {
+        let names: Vec<&str> = USAGE
+            .split_once("Names:")
+            .unwrap()
+            .1
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+            .collect();
+        let keys: Vec<&str> = Overlay::ALL.iter().map(|overlay| overlay.key()).collect();
+        assert_eq!(names, keys);
+    }
+
+    #[test]
+    fn rejects_bad_render_arguments() {
+        let message = usage_error(parse_render_args(&args(&[
+            "a.map",
+            "-o",
+            "a.png",
+            "--overlay",
+            "fog",
+        ])));
+        assert!(message.contains("Unknown overlay"), "{}", message);
+
+        let message = usage_error(parse_render_args(&args(&["a.map"])));
+        assert!(message.contains("No output file"), "{}", message);
+
+        let message = usage_error(parse_render_args(&args(&["-o", "a.png"])));
+        assert!(message.contains("No map"), "{}", message);
+
+        for scale in ["0", "-1", "abc", "inf", "NaN"] {
+            let message = usage_error(parse_render_args(&args(&[
+                "a.map", "-o", "a.png", "--scale", scale,
+            ])));
+            assert!(message.contains("Invalid scale"), "{}", message);
+        }
+
+        let message = usage_error(parse_render_args(&args(&["a.map", "-o"])));
+        assert!(message.contains("needs a value"), "{}", message);
+
+        let message = usage_error(parse_render_args(&args(&["a.map", "--bogus"])));
+        assert!(message.contains("Unknown option"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_scales_too_large_to_allocate() {
+        let map = Map::new(MapInfo {
+            width: 512,
+            height: 256,
+            ..Default::default()
+        });
+        let scaled = |scale| RenderOptions {
+            scale,
+            ..Default::default()
+        };
+        assert!(matches!(
+            render(&map, &scaled(1000.0)),
+            Err(CliError::Usage(_))
+        ));
+        assert!(matches!(
+            checked_output_size(&map, &scaled(2.0)),
+            Err(CliError::Usage(_))
+        ));
+        assert_eq!(
+            checked_output_size(&map, &scaled(1.0)).unwrap(),
+            (16384, 8192)
+        );
+    }
+
+    #[test]
+    fn renders_cell_type_colors_without_tilesets() {
+        let map = checkered_map(3, 2);
+        let options = RenderOptions {
+            scale: 0.25,
+            ..Default::default()
+        };
+        let image = render(&map, &options).unwrap();
+        assert_eq!(image.dimensions(), (24, 16));
+
+        let [r, g, b] = cell_type_rgb(CellType::FastPassible1);
+        assert_eq!(image.get_pixel(0, 0).0, [r, g, b, 255]);
+        assert_eq!(image.get_pixel(15, 15).0, [r, g, b, 255]);
+        let [r, g, b] = cell_type_rgb(CellType::Impassible1);
+        assert_eq!(image.get_pixel(8, 0).0, [r, g, b, 255]);
+        assert_eq!(image.get_pixel(23, 15).0, [r, g, b, 255]);
+    }
+}
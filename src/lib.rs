@@ -0,0 +1,18 @@
+//! OP2MapViewer library: map loading, rendering and the viewer UI
+//!
+//! The binary in `main.rs` starts the viewer or runs a command-line tool;
+//! everything else lives here so benchmarks can use it too.
+
+pub mod lighting;
+pub mod map;
+pub mod render;
+pub mod ui {
+    pub mod app;
+    pub mod cell_info;
+    pub mod image_export;
+    pub mod map_view;
+    pub mod minimap;
+    pub mod overlays;
+    pub mod tile_groups;
+    pub mod tileset_browser;
+}
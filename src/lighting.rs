@@ -0,0 +1,195 @@
+//! Day/night lighting preview
+//!
+//! Outpost 2 darkens the terrain outside a band of daylight that sweeps
+//! across the map from east to west, and structures light up the ground
+//! around them at night. This is an approximation for previewing maps: the
+//! band covers half the map width with a soft edge, and every building tile
+//! or saved-game unit lights a fixed radius.
+
+use crate::map::types::Map;
+
+/// Brightness of cells in full night, from 0 (black) to 1 (unchanged)
+pub const NIGHT_BRIGHTNESS: f32 = 0.3;
+
+/// Width of the dusk and dawn edges of the daylight band, as a fraction of the map width
+const TWILIGHT_WIDTH: f32 = 0.1;
+
+/// Distance in cells that structures light at night
+pub const STRUCTURE_LIGHT_RADIUS: i32 = 4;
+
+/// Brightness of a column of cells when the daylight band is centred at `daylight`
+///
+/// `daylight` is a fraction of the map width; the band wraps around the
+/// edges like the game's day/night cycle.
+pub fn daylight_brightness(column: u32, map_width: u32, daylight: f32) -> f32 {
+    let x = (column as f32 + 0.5) / map_width.max(1) as f32;
+    let distance = (x - daylight.rem_euclid(1.0)).abs();
+    let distance = distance.min(1.0 - distance);
+
+    // Half the map is lit; fade across the twilight edge around a quarter-width away
+    let dusk = ((distance - 0.25) / TWILIGHT_WIDTH + 0.5).clamp(0.0, 1.0);
+    1.0 - dusk * (1.0 - NIGHT_BRIGHTNESS)
+}
+
+/// Light from structures for every cell of the map, in row order
+///
+/// Light source cells get 1, falling off linearly to 0 at
+/// [`STRUCTURE_LIGHT_RADIUS`]. Light sources are cells flagged as a wall or
+/// building that aren't walls, and the cells covered by saved-game units.
+pub fn structure_light(map: &Map) -> Vec<f32> {
+    let width = map.info.width as i32;
+    let height = map.info.height as i32;
+    let mut light = vec![0.0f32; (width * height).max(0) as usize];
+
+    let mut sources: Vec<(i32, i32)> = map
+        .cells()
+        .filter(|cell| cell.tile.is_wall_or_building() && !cell.cell_type().is_wall())
+        .map(|cell| (cell.position.x, cell.position.y))
+        .collect();
+    for unit in &map.units {
+        for y in unit.min.y..=unit.max.y {
+            for x in unit.min.x..=unit.max.x {
+                sources.push((x, y));
+            }
+        }
+    }
+
+    let radius = STRUCTURE_LIGHT_RADIUS;
+    for (source_x, source_y) in sources {
+        for y in (source_y - radius).max(0)..=(source_y + radius).min(height - 1) {
+            for x in (source_x - radius).max(0)..=(source_x + radius).min(width - 1) {
+                let dx = (x - source_x) as f32;
+                let dy = (y - source_y) as f32;
+                let amount = 1.0 - (dx * dx + dy * dy).sqrt() / radius as f32;
+                let cell = &mut light[(y * width + x) as usize];
+                *cell = cell.max(amount);
+            }
+        }
+    }
+    light
+}
+
+/// Combines daylight with structure light, never going darker than the daylight
+pub fn cell_brightness(daylight: f32, structure_light: f32) -> f32 {
+    daylight.max(NIGHT_BRIGHTNESS + (1.0 - NIGHT_BRIGHTNESS) * structure_light)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::types::{CellType, MapInfo, Position, TileWord, Unit};
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn daylight_fades_from_the_band_centre_to_night() {
+        // Column 49 is centred at 0.495 of the width
+        assert_close(daylight_brightness(49, 100, 0.495), 1.0);
+        // Inside the band, before the twilight edge starts
+        assert_close(daylight_brightness(69, 100, 0.495), 1.0);
+        // Halfway through the twilight edge, a quarter of the width away
+        assert_close(
+            daylight_brightness(74, 100, 0.495),
+            (1.0 + NIGHT_BRIGHTNESS) / 2.0,
+        );
+        assert_close(
+            daylight_brightness(24, 100, 0.495),
+            (1.0 + NIGHT_BRIGHTNESS) / 2.0,
+        );
+        // Past the twilight edge
+        assert_close(daylight_brightness(80, 100, 0.495), NIGHT_BRIGHTNESS);
+        assert_close(daylight_brightness(99, 100, 0.495), NIGHT_BRIGHTNESS);
+    }
+
+    #[test]
+    fn daylight_wraps_around_the_map_edges() {
+        // A band centred on the left edge also lights the right edge
+        assert_close(daylight_brightness(0, 100, 0.0), 1.0);
+        assert_close(daylight_brightness(99, 100, 0.0), 1.0);
+        assert_close(daylight_brightness(50, 100, 0.0), NIGHT_BRIGHTNESS);
+
+        // Positions outside 0..1 are the same as their wrapped equivalents
+        for column in [0, 20, 50, 75, 99] {
+            let brightness = daylight_brightness(column, 100, 0.75);
+            assert_close(daylight_brightness(column, 100, -0.25), brightness);
+            assert_close(daylight_brightness(column, 100, 1.75), brightness);
+        }
+    }
+
+    /// A 12x12 map with a building tile at (5, 5) and a wall at (0, 11)
+    fn lit_map() -> Map {
+        let mut map = Map::new(MapInfo {
+            width: 12,
+            height: 12,
+            ..Default::default()
+        });
+        let mut building = TileWord::new(CellType::FastPassible1.index(), 0);
+        building.set_wall_or_building(true);
+        *map.tile_mut(5, 5).unwrap() = building;
+        let mut wall = TileWord::new(CellType::NormalWall.index(), 0);
+        wall.set_wall_or_building(true);
+        *map.tile_mut(0, 11).unwrap() = wall;
+        map
+    }
+
+    #[test]
+    fn structure_light_falls_off_to_the_radius() {
+        let map = lit_map();
+        let light = structure_light(&map);
+        let at = |x: usize, y: usize| light[y * 12 + x];
+
+        assert_close(at(5, 5), 1.0);
+        assert_close(at(6, 5), 0.75);
+        assert_close(at(5, 3), 0.5);
+        assert_close(at(8, 5), 0.25);
+        // Nothing reaches the radius or beyond it, including the corners of
+        // the square around the source
+        assert_close(at(9, 5), 0.0);
+        assert_close(at(5, 1), 0.0);
+        assert_close(at(8, 8), 0.0);
+        assert_close(at(11, 5), 0.0);
+        // Walls don't light anything
+        assert_close(at(0, 11), 0.0);
+        assert_close(at(1, 11), 0.0);
+    }
+
+    #[test]
+    fn units_light_every_cell_they_cover() {
+        let mut map = lit_map();
+        map.units.push(Unit {
+            index: 1,
+            min: Position::new(10, 0),
+            max: Position::new(11, 1),
+            record: None,
+        });
+        let light = structure_light(&map);
+        let at = |x: usize, y: usize| light[y * 12 + x];
+
+        for (x, y) in [(10, 0), (11, 0), (10, 1), (11, 1)] {
+            assert_close(at(x, y), 1.0);
+        }
+        assert_close(at(9, 0), 0.75);
+        assert_close(at(11, 4), 0.25);
+        assert_close(at(11, 5), 0.0);
+    }
+
+    #[test]
+    fn structure_light_only_brightens_the_night() {
+        assert_close(cell_brightness(NIGHT_BRIGHTNESS, 0.0), NIGHT_BRIGHTNESS);
+        assert_close(cell_brightness(NIGHT_BRIGHTNESS, 1.0), 1.0);
+        assert_close(
+            cell_brightness(NIGHT_BRIGHTNESS, 0.5),
+            (1.0 + NIGHT_BRIGHTNESS) / 2.0,
+        );
+        // Daylight brighter than the structure light is kept
+        assert_close(cell_brightness(0.9, 0.25), 0.9);
+        assert_close(cell_brightness(1.0, 0.0), 1.0);
+    }
+}
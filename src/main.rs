@@ -1,17 +1,29 @@
 //! Main entry point for OP2MapViewer
 
-use eframe::egui;
+mod cli;
 
-mod map;
-mod ui {
-    pub mod app;
-    pub mod cell_info;
-    pub mod map_view;
-}
+use std::path::PathBuf;
+
+use eframe::egui;
 
-use ui::app::MapViewerApp;
+use op2mapviewerrust::ui::app::MapViewerApp;
 
 fn main() -> eframe::Result<()> {
+    // macOS passes a process serial number to apps started from Finder
+    let args: Vec<String> = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("-psn_"))
+        .collect();
+
+    // A known command runs the command-line tool, a single path opens that
+    // map in the viewer, e.g. when a map is dropped on the executable
+    let initial_map = match args.as_slice() {
+        [] => None,
+        [command, ..] if cli::is_command(command) => std::process::exit(cli::run(&args)),
+        [path] => Some(PathBuf::from(path)),
+        _ => std::process::exit(cli::run(&args)),
+    };
+
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([1024.0, 768.0])
@@ -24,6 +36,12 @@ fn main() -> eframe::Result<()> {
     eframe::run_native(
         "OP2MapViewer",
         options,
-        Box::new(|cc| Box::new(MapViewerApp::new(cc))),
+        Box::new(move |cc| {
+            let mut app = MapViewerApp::new(cc);
+            if let Some(path) = initial_map {
+                app.open_path(path);
+            }
+            Box::new(app)
+        }),
     )
 }
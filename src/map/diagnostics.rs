@@ -0,0 +1,117 @@
+//! Diagnostics collected while loading maps and tilesets
+//!
+//! Loaders record what they found and where things went wrong instead of
+//! printing to stdout, so the report can be shown to map authors.
+
+use std::fmt;
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A single message from a loader
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Part of the file the message is about, such as "tile mappings"
+    pub section: String,
+    /// Byte offset into the file, if known
+    pub offset: Option<u64>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}", self.severity, self.section)?;
+        if let Some(offset) = self.offset {
+            write!(f, " @ {:#x}", offset)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Collects diagnostics in the order they were reported
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    entries: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(
+        &mut self,
+        severity: Severity,
+        section: impl Into<String>,
+        offset: Option<u64>,
+        message: impl Into<String>,
+    ) {
+        self.entries.push(Diagnostic {
+            severity,
+            section: section.into(),
+            offset,
+            message: message.into(),
+        });
+    }
+
+    pub fn info(
+        &mut self,
+        section: impl Into<String>,
+        offset: Option<u64>,
+        message: impl Into<String>,
+    ) {
+        self.push(Severity::Info, section, offset, message);
+    }
+
+    pub fn warning(
+        &mut self,
+        section: impl Into<String>,
+        offset: Option<u64>,
+        message: impl Into<String>,
+    ) {
+        self.push(Severity::Warning, section, offset, message);
+    }
+
+    pub fn error(
+        &mut self,
+        section: impl Into<String>,
+        offset: Option<u64>,
+        message: impl Into<String>,
+    ) {
+        self.push(Severity::Error, section, offset, message);
+    }
+
+    pub fn entries(&self) -> &[Diagnostic] {
+        &self.entries
+    }
+
+    /// Number of diagnostics with the given severity
+    pub fn count(&self, severity: Severity) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.severity == severity)
+            .count()
+    }
+
+    /// Most severe level reported, if anything was reported
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.entries.iter().map(|entry| entry.severity).max()
+    }
+}
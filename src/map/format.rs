@@ -0,0 +1,345 @@
+//! Pluggable map formats
+//!
+//! Each supported file format implements [`MapFormat`]. A [`FormatRegistry`]
+//! picks the formats worth trying for a file, first by magic bytes and then by
+//! extension, and tries them in turn until one succeeds.
+
+use std::io::Write;
+use std::path::Path;
+
+use super::diagnostics::Diagnostics;
+use super::json::JsonFormat;
+use super::loader::{map_name_from_path, Form2Format, MapLoadError, NativeFormat};
+use super::types::Map;
+use super::vol;
+
+/// A file format maps can be loaded from and, optionally, saved to
+pub trait MapFormat {
+    /// Short name used in reports, such as "native"
+    fn name(&self) -> &'static str;
+
+    /// Lowercase file extensions commonly used by the format
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Returns true if the data looks like this format
+    fn sniff(&self, data: &[u8]) -> bool;
+
+    /// Loads a map from the file contents
+    fn load(&self, data: &[u8], diagnostics: &mut Diagnostics) -> Result<Map, MapLoadError>;
+
+    /// Returns true if [`MapFormat::save`] is supported
+    fn can_save(&self) -> bool {
+        false
+    }
+
+    /// Writes a map in this format
+    fn save(&self, _map: &Map, _writer: &mut dyn Write) -> Result<(), MapLoadError> {
+        Err(MapLoadError::InvalidFormat(format!(
+            "Saving {} maps is not supported",
+            self.name()
+        )))
+    }
+}
+
+/// The error a single format gave while loading a map
+#[derive(Debug)]
+pub struct FormatError {
+    pub format: &'static str,
+    pub error: MapLoadError,
+}
+
+/// The set of map formats known to the viewer
+pub struct FormatRegistry {
+    formats: Vec<Box<dyn MapFormat>>,
+}
+
+impl Default for FormatRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(Box::new(NativeFormat));
+        registry.register(Box::new(Form2Format));
+        registry.register(Box::new(JsonFormat));
+        registry
+    }
+}
+
+impl FormatRegistry {
+    /// Creates a registry without any formats
+    pub fn new() -> Self {
+        Self {
+            formats: Vec::new(),
+        }
+    }
+
+    /// Adds a format; formats registered earlier are tried first
+    pub fn register(&mut self, format: Box<dyn MapFormat>) {
+        self.formats.push(format);
+    }
+
+    pub fn formats(&self) -> impl Iterator<Item = &dyn MapFormat> {
+        self.formats.iter().map(|format| format.as_ref())
+    }
+
+    /// Formats worth trying for a file: those recognising the data first,
+    /// then those registered for the file's extension
+    fn candidates<'a>(&'a self, data: &[u8], file_path: &Path) -> Vec<&'a dyn MapFormat> {
+        let extension = file_extension(file_path);
+        let sniffed = self.formats().filter(|format| format.sniff(data));
+        let by_extension = self.formats().filter(|format| {
+            !format.sniff(data)
+                && extension
+                    .as_deref()
+                    .is_some_and(|ext| format.extensions().contains(&ext))
+        });
+        sniffed.chain(by_extension).collect()
+    }
+
+    /// Loads a map, returning it along with the name of the format that read it
+    ///
+    /// If no format succeeds, the error lists what each format reported.
+    pub fn load(
+        &self,
+        file_path: &Path,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<(Map, &'static str), MapLoadError> {
+        diagnostics.info("file", None, format!("Loading {}", file_path.display()));
+
+        // Maps may live inside a VOL archive ("maps.vol:on6_01.map")
+        let data = vol::read_path(file_path)
+            .inspect_err(|err| diagnostics.error("file", None, err.to_string()))?;
+
+        let candidates = self.candidates(&data, file_path);
+        if candidates.is_empty() {
+            diagnostics.error("file", None, "No map format recognises this file");
+        }
+
+        let mut errors = Vec::new();
+        for format in candidates {
+            match format.load(&data, diagnostics) {
+                Ok(mut map) => {
+                    if map.info.name.is_empty() {
+                        map.info.name = map_name_from_path(file_path);
+                    }
+                    map.info.source_format = Some(format.name());
+                    let message = format!(
+                        "Loaded {}x{} map using the {} format",
+                        map.info.width,
+                        map.info.height,
+                        format.name()
+                    );
+                    // Falling back past a format that should have worked is worth a look
+                    if errors.is_empty() {
+                        diagnostics.info("file", None, message);
+                    } else {
+                        diagnostics.warning("file", None, message);
+                    }
+                    return Ok((map, format.name()));
+                }
+                Err(error) => {
+                    diagnostics.error(
+                        "file",
+                        None,
+                        format!("The {} format failed: {}", format.name(), error),
+                    );
+                    errors.push(FormatError {
+                        format: format.name(),
+                        error,
+                    });
+                }
+            }
+        }
+
+        Err(MapLoadError::NoMatchingFormat(errors))
+    }
+
+    /// Saves a map in the format registered for the file's extension, or in the
+    /// first format that can save if none is
+    ///
+    /// The map is serialised in memory first, so a map the format rejects
+    /// leaves an existing file untouched.
+    pub fn save(&self, map: &Map, file_path: &Path) -> Result<&'static str, MapLoadError> {
+        let extension = file_extension(file_path);
+        let format = self
+            .formats()
+            .filter(|format| format.can_save())
+            .find(|format| {
+                extension
+                    .as_deref()
+                    .is_some_and(|ext| format.extensions().contains(&ext))
+            })
+            .or_else(|| self.formats().find(|format| format.can_save()))
+            .ok_or_else(|| {
+                MapLoadError::InvalidFormat(format!(
+                    "No map format can save {}",
+                    file_path.display()
+                ))
+            })?;
+
+        let mut data = Vec::new();
+        format.save(map, &mut data)?;
+        std::fs::write(file_path, data)?;
+        Ok(format.name())
+    }
+}
+
+/// Lowercase extension of a file, looking inside `archive.vol:file` paths
+fn file_extension(file_path: &Path) -> Option<String> {
+    let name = vol::split_vol_path(file_path)
+        .map(|(_, name)| name)
+        .or_else(|| file_path.to_str().map(str::to_string))?;
+    Path::new(&name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::types::MapInfo;
+    use std::path::PathBuf;
+
+    /// A format that recognises data starting with `magic` and fails to load
+    /// when `fails` is set
+    struct TestFormat {
+        name: &'static str,
+        extensions: &'static [&'static str],
+        magic: &'static [u8],
+        fails: bool,
+    }
+
+    impl MapFormat for TestFormat {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn extensions(&self) -> &'static [&'static str] {
+            self.extensions
+        }
+
+        fn sniff(&self, data: &[u8]) -> bool {
+            data.starts_with(self.magic)
+        }
+
+        fn load(&self, _data: &[u8], _diagnostics: &mut Diagnostics) -> Result<Map, MapLoadError> {
+            if self.fails {
+                return Err(MapLoadError::InvalidFormat(format!("{} failed", self.name)));
+            }
+            Ok(Map::new(MapInfo {
+                width: 1,
+                height: 1,
+                ..Default::default()
+            }))
+        }
+    }
+
+    fn registry(formats: [TestFormat; 2]) -> FormatRegistry {
+        let mut registry = FormatRegistry::new();
+        for format in formats {
+            registry.register(Box::new(format));
+        }
+        registry
+    }
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("op2mapviewer-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn prefers_the_format_recognising_the_data() {
+        let registry = registry([
+            TestFormat {
+                name: "by extension",
+                extensions: &["aaa"],
+                magic: b"AAAA",
+                fails: false,
+            },
+            TestFormat {
+                name: "by magic",
+                extensions: &["bbb"],
+                magic: b"BBBB",
+                fails: false,
+            },
+        ]);
+        let path = temp_file("sniffed.aaa", b"BBBB data");
+
+        let (map, format) = registry.load(&path, &mut Diagnostics::new()).unwrap();
+        assert_eq!(format, "by magic");
+        assert_eq!(map.info.source_format, Some("by magic"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn falls_back_to_the_extension() {
+        let registry = registry([
+            TestFormat {
+                name: "first",
+                extensions: &["aaa"],
+                magic: b"AAAA",
+                fails: false,
+            },
+            TestFormat {
+                name: "second",
+                extensions: &["bbb"],
+                magic: b"BBBB",
+                fails: false,
+            },
+        ]);
+        let path = temp_file("fallback.BBB", b"no magic here");
+
+        let (_, format) = registry.load(&path, &mut Diagnostics::new()).unwrap();
+        assert_eq!(format, "second");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reports_one_error_per_attempted_format() {
+        let registry = registry([
+            TestFormat {
+                name: "sniffed",
+                extensions: &["aaa"],
+                magic: b"AAAA",
+                fails: true,
+            },
+            TestFormat {
+                name: "extension",
+                extensions: &["bbb"],
+                magic: b"BBBB",
+                fails: true,
+            },
+        ]);
+        let path = temp_file("failing.bbb", b"AAAA data");
+
+        let mut diagnostics = Diagnostics::new();
+        match registry.load(&path, &mut diagnostics) {
+            Err(MapLoadError::NoMatchingFormat(errors)) => {
+                let formats: Vec<_> = errors.iter().map(|error| error.format).collect();
+                assert_eq!(formats, ["sniffed", "extension"]);
+                assert!(errors
+                    .iter()
+                    .all(|error| matches!(error.error, MapLoadError::InvalidFormat(_))));
+            }
+            other => panic!("expected NoMatchingFormat, got {:?}", other.map(|(_, f)| f)),
+        }
+        assert_eq!(diagnostics.count(crate::map::Severity::Error), 2);
+
+        // A file no format claims is reported with an empty list
+        let unclaimed = temp_file("unclaimed.ccc", b"data");
+        assert!(matches!(
+            registry.load(&unclaimed, &mut Diagnostics::new()),
+            Err(MapLoadError::NoMatchingFormat(errors)) if errors.is_empty()
+        ));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&unclaimed).unwrap();
+    }
+
+    #[test]
+    fn load_map_records_the_format() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("on6_01.map");
+        let map = crate::map::load_map(&path, &mut Diagnostics::new()).unwrap();
+        assert_eq!(map.info.source_format, Some("native"));
+    }
+}
@@ -0,0 +1,440 @@
+//! JSON import and export of maps
+//!
+//! Maps are written as a single JSON object so they can be kept as text in
+//! version control and saved back as `.map` files later. The object has these
+//! fields, in this order:
+//!
+//! - `format`: always `"op2mapviewer-map"`
+//! - `version`: schema version, currently 1
+//! - `info`: the [`MapInfo`] header
+//! - `clip_rect`: the scrollable area, `{ "x1", "y1", "x2", "y2" }`
+//! - `tilesets`: the tileset table; unused slots have an empty `filename`
+//! - `tile_mappings`: `{ "tileset_index", "tile_graphic_index", "animation_count", "animation_delay" }`
+//! - `terrain_types`: the terrain type table, field for field as in the map file
+//! - `tile_groups`: `{ "width", "height", "mapping_indices", "name" }`; names that
+//!   aren't printable text are written as `{ "hex": "..." }`
+//! - `tile_groups_unknown`: the value stored after the tile group count
+//! - `tiles`: one string per row, holding the row's tile words as 8-digit hex
+//!   numbers separated by spaces
+//! - `heights`, `cell_flags` (optional): one string per row of 2-digit hex bytes,
+//!   for formats that store cell heights; flag bit 0 marks wreckage and bit 1 a unit
+//! - `units` (optional): units of a saved game, with their raw records in hex
+//!
+//! Tile words keep the game's bit layout described on [`TileWord`]: bits 0-4
+//! hold the cell type, bits 5-15 the tile mapping index, bits 16-26 the unit
+//! index and the top bits the lava, microbe and wall flags.
+
+use std::fmt::Write as _;
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use super::diagnostics::Diagnostics;
+use super::format::MapFormat;
+use super::loader::MapLoadError;
+use super::types::{
+    Cell, ClipRect, Map, MapInfo, TerrainType, TileGroup, TileMapping, TileWord, TilesetSource,
+    Unit, MAX_MAP_DIMENSION,
+};
+
+/// Value of the `format` field
+const FORMAT_NAME: &str = "op2mapviewer-map";
+
+/// Current value of the `version` field
+const FORMAT_VERSION: u32 = 1;
+
+/// The serialised form of a [`Map`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MapDocument {
+    pub format: String,
+    pub version: u32,
+    pub info: MapInfo,
+    pub clip_rect: ClipRect,
+    pub tilesets: Vec<TilesetSource>,
+    pub tile_mappings: Vec<TileMapping>,
+    pub terrain_types: Vec<TerrainType>,
+    pub tile_groups: Vec<TileGroup>,
+    #[serde(default)]
+    pub tile_groups_unknown: u32,
+    pub tiles: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heights: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cell_flags: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub units: Vec<Unit>,
+}
+
+impl From<Map> for MapDocument {
+    fn from(map: Map) -> Self {
+        let rows = || 0..map.info.height as i32;
+        let tiles = rows()
+            .map(|y| {
+                let words: Vec<String> = (0..map.info.width as i32)
+                    .filter_map(|x| map.tile(x, y))
+                    .map(|tile| format!("{:08x}", tile.raw()))
+                    .collect();
+                words.join(" ")
+            })
+            .collect();
+
+        let (heights, cell_flags) = if map.has_cell_details() {
+            let row_bytes = |y: i32, byte: &dyn Fn(Cell) -> u8| {
+                let bytes: Vec<u8> = (0..map.info.width as i32)
+                    .filter_map(|x| map.get_cell(x, y))
+                    .map(byte)
+                    .collect();
+                to_hex(&bytes)
+            };
+            let heights = rows().map(|y| row_bytes(y, &|cell| cell.height)).collect();
+            let flags = rows()
+                .map(|y| {
+                    row_bytes(y, &|cell| {
+                        cell.has_wreckage as u8 | (cell.has_unit as u8) << 1
+                    })
+                })
+                .collect();
+            (Some(heights), Some(flags))
+        } else {
+            (None, None)
+        };
+
+        Self {
+            format: FORMAT_NAME.to_string(),
+            version: FORMAT_VERSION,
+            info: map.info,
+            clip_rect: map.clip_rect,
+            tilesets: map.tileset_sources,
+            tile_mappings: map.tile_mappings,
+            terrain_types: map.terrain_types,
+            tile_groups: map.tile_groups,
+            tile_groups_unknown: map.tile_groups_unknown,
+            tiles,
+            heights,
+            cell_flags,
+            units: map.units,
+        }
+    }
+}
+
+impl TryFrom<MapDocument> for Map {
+    type Error = MapLoadError;
+
+    fn try_from(document: MapDocument) -> Result<Self, Self::Error> {
+        if document.format != FORMAT_NAME {
+            return Err(MapLoadError::InvalidFormat(format!(
+                "Expected format \"{}\", found \"{}\"",
+                FORMAT_NAME, document.format
+            )));
+        }
+        if document.version != FORMAT_VERSION {
+            return Err(MapLoadError::UnsupportedVersion(document.version));
+        }
+
+        let width = document.info.width;
+        let height = document.info.height;
+        if !(1..=MAX_MAP_DIMENSION).contains(&width) || !(1..=MAX_MAP_DIMENSION).contains(&height) {
+            return Err(MapLoadError::InvalidFormat(format!(
+                "Invalid map size {}x{}",
+                width, height
+            )));
+        }
+        check_row_count("tiles", &document.tiles, height)?;
+
+        let mut map = Map::new(document.info);
+        for (y, row) in document.tiles.iter().enumerate() {
+            let words: Vec<&str> = row.split_whitespace().collect();
+            if words.len() != width as usize {
+                return Err(row_error("tiles", y, "tile words", words.len(), width));
+            }
+            for (x, word) in words.into_iter().enumerate() {
+                let raw = u32::from_str_radix(word, 16).map_err(|_| {
+                    MapLoadError::InvalidFormat(format!(
+                        "Invalid tile word \"{}\" at ({}, {})",
+                        word, x, y
+                    ))
+                })?;
+                if let Some(tile) = map.tile_mut(x as i32, y as i32) {
+                    *tile = TileWord(raw);
+                }
+            }
+        }
+
+        if let (Some(heights), Some(flags)) = (&document.heights, &document.cell_flags) {
+            check_row_count("heights", heights, height)?;
+            check_row_count("cell_flags", flags, height)?;
+            for (y, (height_row, flag_row)) in heights.iter().zip(flags).enumerate() {
+                let heights = from_hex(height_row).map_err(MapLoadError::InvalidFormat)?;
+                let flags = from_hex(flag_row).map_err(MapLoadError::InvalidFormat)?;
+                if heights.len() != width as usize {
+                    return Err(row_error("heights", y, "bytes", heights.len(), width));
+                }
+                if flags.len() != width as usize {
+                    return Err(row_error("cell_flags", y, "bytes", flags.len(), width));
+                }
+                for (x, (&height, &flags)) in heights.iter().zip(&flags).enumerate() {
+                    map.set_cell_details(
+                        x as i32,
+                        y as i32,
+                        height,
+                        flags & 1 != 0,
+                        flags & 2 != 0,
+                    );
+                }
+            }
+        }
+
+        map.clip_rect = document.clip_rect;
+        map.tileset_sources = document.tilesets;
+        map.tile_mappings = document.tile_mappings;
+        map.terrain_types = document.terrain_types;
+        map.tile_groups = document.tile_groups;
+        map.tile_groups_unknown = document.tile_groups_unknown;
+        map.units = document.units;
+        map.units.sort_by_key(|unit| unit.index);
+        Ok(map)
+    }
+}
+
+fn check_row_count(field: &str, rows: &[String], height: u32) -> Result<(), MapLoadError> {
+    if rows.len() != height as usize {
+        return Err(MapLoadError::InvalidFormat(format!(
+            "\"{}\" has {} rows but the map is {} high",
+            field,
+            rows.len(),
+            height
+        )));
+    }
+    Ok(())
+}
+
+fn row_error(field: &str, y: usize, what: &str, found: usize, width: u32) -> MapLoadError {
+    MapLoadError::InvalidFormat(format!(
+        "Row {} of \"{}\" has {} {} but the map is {} wide",
+        y, field, found, what, width
+    ))
+}
+
+/// Maps exported by the viewer as JSON
+pub struct JsonFormat;
+
+impl MapFormat for JsonFormat {
+    fn name(&self) -> &'static str {
+        "JSON"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["json"]
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        // `format` is the first field the exporter writes
+        let start = &data[..data.len().min(256)];
+        start.trim_ascii_start().starts_with(b"{")
+            && start
+                .windows(FORMAT_NAME.len())
+                .any(|window| window == FORMAT_NAME.as_bytes())
+    }
+
+    fn load(&self, data: &[u8], diagnostics: &mut Diagnostics) -> Result<Map, MapLoadError> {
+        let map: Map = serde_json::from_slice(data)
+            .inspect_err(|err| diagnostics.error("json", None, err.to_string()))?;
+        diagnostics.info(
+            "json",
+            None,
+            format!(
+                "{} tile mappings, {} terrain types, {} tile groups",
+                map.tile_mappings.len(),
+                map.terrain_types.len(),
+                map.tile_groups.len()
+            ),
+        );
+        Ok(map)
+    }
+
+    fn can_save(&self) -> bool {
+        true
+    }
+
+    fn save(&self, map: &Map, mut writer: &mut dyn Write) -> Result<(), MapLoadError> {
+        serde_json::to_writer_pretty(&mut writer, map)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(format!("Invalid hex string \"{}\"", hex));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("Invalid hex string \"{}\"", hex))
+        })
+        .collect()
+}
+
+/// Serialises bytes as a string when they are printable text, and as
+/// `{ "hex": "..." }` otherwise
+pub(super) mod text_or_hex {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum TextOrHex {
+        Text(String),
+        Hex { hex: String },
+    }
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) if !text.chars().any(char::is_control) => {
+                TextOrHex::Text(text.to_string()).serialize(serializer)
+            }
+            _ => TextOrHex::Hex {
+                hex: super::to_hex(bytes),
+            }
+            .serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        match TextOrHex::deserialize(deserializer)? {
+            TextOrHex::Text(text) => Ok(text.into_bytes()),
+            TextOrHex::Hex { hex } => super::from_hex(&hex).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Serialises optional bytes as a hex string or `null`
+pub(super) mod hex_option {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_some(&super::to_hex(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|hex| super::from_hex(&hex).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A valid document for a 2x2 map with heights and cell flags
+    fn document() -> MapDocument {
+        let mut map = Map::new(MapInfo {
+            width: 2,
+            height: 2,
+            ..Default::default()
+        });
+        *map.tile_mut(1, 0).unwrap() = TileWord::new(3, 7);
+        map.set_cell_details(0, 1, 40, true, false);
+        MapDocument::from(map)
+    }
+
+    fn invalid_format(document: MapDocument) -> String {
+        match Map::try_from(document) {
+            Err(MapLoadError::InvalidFormat(message)) => message,
+            Err(other) => panic!("expected InvalidFormat, got {}", other),
+            Ok(_) => panic!("expected InvalidFormat"),
+        }
+    }
+
+    #[test]
+    fn converts_a_valid_document() {
+        let document = document();
+        assert_eq!(document.tiles, ["00000000 000000e3", "00000000 00000000"]);
+        assert_eq!(
+            document.heights.as_deref(),
+            Some(&["0000".into(), "2800".into()][..])
+        );
+
+        let map = Map::try_from(document).unwrap();
+        assert_eq!(map.tile(1, 0), Some(TileWord::new(3, 7)));
+        let cell = map.get_cell(0, 1).unwrap();
+        assert_eq!(
+            (cell.height, cell.has_wreckage, cell.has_unit),
+            (40, true, false)
+        );
+    }
+
+    #[test]
+    fn rejects_other_schemas_and_versions() {
+        let mut wrong_format = document();
+        wrong_format.format = "op2mapviewer-tileset".into();
+        assert!(invalid_format(wrong_format).contains("Expected format"));
+
+        let mut wrong_version = document();
+        wrong_version.version = FORMAT_VERSION + 1;
+        assert!(matches!(
+            Map::try_from(wrong_version),
+            Err(MapLoadError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[test]
+    fn rejects_sizes_outside_the_native_limits() {
+        for (width, height) in [(0, 2), (2, 0), (MAX_MAP_DIMENSION + 1, 2), (2, u32::MAX)] {
+            let mut document = document();
+            document.info.width = width;
+            document.info.height = height;
+            assert!(invalid_format(document).contains("Invalid map size"));
+        }
+    }
+
+    #[test]
+    fn rejects_rows_of_the_wrong_length() {
+        let mut short_tiles = document();
+        short_tiles.tiles[1] = "00000000".into();
+        assert!(invalid_format(short_tiles).contains("has 1 tile words"));
+
+        let mut missing_row = document();
+        missing_row.tiles.pop();
+        assert!(invalid_format(missing_row).contains("has 1 rows"));
+
+        let mut long_heights = document();
+        long_heights.heights.as_mut().unwrap()[0] = "000000".into();
+        assert!(invalid_format(long_heights).contains("has 3 bytes"));
+
+        let mut odd_flags = document();
+        odd_flags.cell_flags.as_mut().unwrap()[1] = "010".into();
+        assert!(invalid_format(odd_flags).contains("Invalid hex string"));
+    }
+
+    #[test]
+    fn rejects_bad_hex_digits() {
+        let mut bad_tile = document();
+        bad_tile.tiles[0] = "00000000 0000zz00".into();
+        assert!(invalid_format(bad_tile).contains("Invalid tile word \"0000zz00\" at (1, 0)"));
+
+        let mut bad_height = document();
+        bad_height.heights.as_mut().unwrap()[1] = "2g00".into();
+        assert!(invalid_format(bad_height).contains("Invalid hex string"));
+
+        assert!(from_hex("ü0").is_err());
+        assert_eq!(from_hex("00ff7A"), Ok(vec![0x00, 0xFF, 0x7A]));
+    }
+}
@@ -1,15 +1,24 @@
 //! Map loading functionality for OP2MapViewer
 
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{self, Read, Seek, SeekFrom};
-use std::path::Path;
+use std::io::{self, Cursor, Read, Seek, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use op2utility_rs::map::Map as Op2Map;
 use thiserror::Error;
 use zip::ZipArchive;
 
-use super::types::{Cell, CellType, Map, MapInfo, Position, TileInfo};
+use super::diagnostics::Diagnostics;
+use super::format::{FormatError, FormatRegistry, MapFormat};
+use super::tileset;
+use super::types::{
+    ClipRect, Form2Cell, Form2Kind, Map, MapInfo, MappingRange, TerrainType, TileGroup,
+    TileMapping, TileWord, TilesetSource, Unit, MAX_MAP_DIMENSION,
+};
+use super::vol::{self, VolArchive};
+use super::writer::write_map;
 
 /// Error type for map loading operations
 #[derive(Error, Debug)]
@@ -31,162 +40,729 @@ pub enum MapLoadError {
 
     #[error("Image error: {0}")]
     ImageError(#[from] image::ImageError),
+
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("VOL archive error: {0}")]
+    VolError(String),
+
+    #[error("No map format could load the file{}", format_errors(.0))]
+    NoMatchingFormat(Vec<FormatError>),
+}
+
+/// Lists the error each format gave, for [`MapLoadError::NoMatchingFormat`]
+fn format_errors(errors: &[FormatError]) -> String {
+    errors
+        .iter()
+        .map(|error| format!("; {}: {}", error.format, error.error))
+        .collect()
+}
+
+/// Version tag written by Outpost 2 into every map and saved game
+pub(super) const MAP_VERSION_TAG: u32 = 0x1011;
+
+/// Marker between the tileset source table and the tile mapping table
+pub(super) const TILESET_TAG: &[u8; 10] = b"TILE SET\x1a\0";
+
+/// Tiles are stored in vertical strips this many columns wide
+const TILE_COLUMN_BLOCK: u32 = 32;
+
+/// Allowed range for the log2 of the map width (32 to 512 tiles)
+pub(super) const LG_WIDTH_RANGE: std::ops::RangeInclusive<u32> = 5..=9;
+
+/// Tile words store the mapping index in 11 bits
+const MAX_TILE_MAPPINGS: u32 = 1 << 11;
+
+/// Tile words store the unit index in 11 bits
+const MAX_UNITS: u32 = 1 << 11;
+
+/// Saved games (.OP2) start with a fixed-size block (save description,
+/// game options and so on) before the map data
+const SAVED_GAME_HEADER_SIZE: usize = 0x1E025;
+
+/// Largest unit table record we accept when reading saved games
+const MAX_UNIT_RECORD_SIZE: u32 = 1024;
+
+/// Size of an entry in the first object array before the unit records
+const UNIT_TABLE_OBJECT_SIZE: usize = 512;
+
+/// Fixed header at the start of an Outpost 2 map file
+#[derive(Debug, Clone, Copy)]
+struct MapHeader {
+    version_tag: u32,
+    is_saved_game: bool,
+    lg_width: u32,
+    height: u32,
+    tileset_count: u32,
 }
 
 /// Attempts to load a map from the given file path
-/// This function will try multiple formats:
-/// 1. First try with the original OP2 map format (for the sample map)
-/// 2. If that fails, try with op2utility_rs library
-pub fn load_map(file_path: &Path) -> Result<Map, MapLoadError> {
-    let file = File::open(file_path)?;
-
-    // First try loading with our custom implementation
-    println!("Attempting to load map: {:?}", file_path);
-    println!("First trying with custom format loader...");
-    match load_original_map_format(file) {
-        Ok(map) => {
-            println!("SUCCESS: Map loaded using original format");
-            println!("Map dimensions: {}x{}", map.info.width, map.info.height);
-            return Ok(map);
-        }
-        Err(err) => {
-            println!("FAILED: Could not load with original format: {:?}", err);
-            println!("Trying with op2utility_rs library...");
-            // If this fails, try with op2utility_rs
-            let file = File::open(file_path)?; // Reopen the file
-            match Op2Map::load(file) {
-                Ok(op2_map) => {
-                    let (width, height) = op2_map.dimensions();
-                    println!("SUCCESS: Map loaded using op2utility_rs");
-                    println!("Map dimensions: {}x{}", width, height);
-                    return convert_op2_map(op2_map, file_path);
-                }
-                Err(err) => {
-                    println!("FAILED: Could not load with op2utility_rs: {:?}", err);
-                    println!("All loading methods failed.");
-                    // Both methods failed, return the error from op2utility_rs
-                    return Err(MapLoadError::Op2UtilityError(err));
-                }
-            }
+///
+/// The file is offered to each registered [`MapFormat`] that recognises its
+/// contents or extension: the native Outpost 2 format (maps and saved games),
+/// FORM2 maps and JSON exports.
+///
+/// The name of the format that read the map is kept in
+/// [`MapInfo::source_format`].
+///
+/// Progress and problems are recorded in `diagnostics`, including when loading fails.
+pub fn load_map(file_path: &Path, diagnostics: &mut Diagnostics) -> Result<Map, MapLoadError> {
+    FormatRegistry::default()
+        .load(file_path, diagnostics)
+        .map(|(map, _)| map)
+}
+
+/// The map and saved game layout written by Outpost 2 itself
+pub struct NativeFormat;
+
+impl MapFormat for NativeFormat {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["map", "op2"]
+    }
+
+    fn sniff(&self, data: &[u8]) -> bool {
+        let tag = MAP_VERSION_TAG.to_le_bytes();
+        data.get(map_data_offset(data)..)
+            .is_some_and(|map_data| map_data.starts_with(&tag))
+    }
+
+    fn load(&self, data: &[u8], diagnostics: &mut Diagnostics) -> Result<Map, MapLoadError> {
+        let map_offset = map_data_offset(data);
+        if map_offset > 0 {
+            diagnostics.info(
+                "saved game header",
+                Some(0),
+                format!("Skipped {:#x} byte saved game header", map_offset),
+            );
         }
+        let reader = OffsetReader::new(&data[map_offset..], map_offset as u64);
+        load_op2_map_format(reader, diagnostics)
+    }
+
+    fn can_save(&self) -> bool {
+        true
+    }
+
+    fn save(&self, map: &Map, mut writer: &mut dyn Write) -> Result<(), MapLoadError> {
+        write_map(map, &mut writer)
     }
 }
 
-/// Loads a map using the original format (for the sample map)
-fn load_original_map_format<R: Read + Seek>(mut reader: R) -> Result<Map, MapLoadError> {
-    // Read the first 4 bytes to check for FORM2 tag
-    let mut header = [0u8; 8];
-    reader.read_exact(&mut header)?;
+/// Maps in the FORM2 layout
+pub struct Form2Format;
 
-    // Reset position to start of file
-    reader.seek(SeekFrom::Start(0))?;
+impl MapFormat for Form2Format {
+    fn name(&self) -> &'static str {
+        "FORM2"
+    }
 
-    // Check if this is a "FORM2" map file
-    if &header[0..5] == b"FORM2" {
-        println!("Detected FORM2 map format");
-        return load_form2_map(reader);
+    fn extensions(&self) -> &'static [&'static str] {
+        &[]
     }
 
-    println!(
-        "Detected sample map format with header bytes: {:?}",
-        &header[0..8]
-    );
+    fn sniff(&self, data: &[u8]) -> bool {
+        data.starts_with(b"FORM2")
+    }
 
-    // Otherwise assume it's the sample map format
-    let mut magic_and_version = [0u8; 8];
-    reader.read_exact(&mut magic_and_version)?;
+    fn load(&self, data: &[u8], diagnostics: &mut Diagnostics) -> Result<Map, MapLoadError> {
+        load_form2_map(Cursor::new(data), diagnostics)
+    }
+}
 
-    // Read map dimensions
-    let mut dimensions = [0u8; 8];
-    reader.read_exact(&mut dimensions)?;
-    let width = u32::from_le_bytes([dimensions[0], dimensions[1], dimensions[2], dimensions[3]]);
-    let height = u32::from_le_bytes([dimensions[4], dimensions[5], dimensions[6], dimensions[7]]);
+/// Maps read through the op2utility_rs library
+///
+/// The library has no cheap signature check, so it is only tried for files
+/// with a map extension. It can confirm that a file parses, but see
+/// [`convert_op2_map`] for why it can't produce a map, which is why
+/// [`FormatRegistry::default`] leaves it out.
+pub struct Op2UtilityFormat;
+
+impl MapFormat for Op2UtilityFormat {
+    fn name(&self) -> &'static str {
+        "op2utility_rs"
+    }
 
-    if width == 0 || height == 0 || width > 1024 || height > 1024 {
-        println!("ERROR: Invalid map dimensions: {}x{}", width, height);
-        return Err(MapLoadError::InvalidFormat(format!(
-            "Invalid map dimensions: {}x{}",
-            width, height
-        )));
+    fn extensions(&self) -> &'static [&'static str] {
+        &["map"]
     }
 
-    println!("Map dimensions: {}x{}", width, height);
+    fn sniff(&self, _data: &[u8]) -> bool {
+        false
+    }
+
+    fn load(&self, data: &[u8], _diagnostics: &mut Diagnostics) -> Result<Map, MapLoadError> {
+        let op2_map = Op2Map::load(Cursor::new(data))?;
+        convert_op2_map(op2_map)
+    }
+}
+
+/// Derives a display name for a map from its file name
+pub(super) fn map_name_from_path(file_path: &Path) -> String {
+    let inner_path = vol::split_vol_path(file_path).map(|(_, name)| PathBuf::from(name));
+    inner_path
+        .as_deref()
+        .unwrap_or(file_path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("Unnamed Map")
+        .to_string()
+}
+
+/// Offset of the map data in a file, skipping the block that precedes it in saved games
+///
+/// Plain maps start directly with the version tag.
+fn map_data_offset(data: &[u8]) -> usize {
+    let tag = MAP_VERSION_TAG.to_le_bytes();
+    let is_saved_game = !data.starts_with(&tag)
+        && data
+            .get(SAVED_GAME_HEADER_SIZE..)
+            .is_some_and(|map_data| map_data.starts_with(&tag));
+    if is_saved_game {
+        SAVED_GAME_HEADER_SIZE
+    } else {
+        0
+    }
+}
+
+/// Reader that keeps track of its byte offset into the file for diagnostics
+struct OffsetReader<R> {
+    inner: R,
+    offset: u64,
+}
+
+impl<R: Read> OffsetReader<R> {
+    fn new(inner: R, offset: u64) -> Self {
+        Self { inner, offset }
+    }
+
+    fn offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+impl<R: Read> Read for OffsetReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.offset += read as u64;
+        Ok(read)
+    }
+}
+
+/// Reads one section of a map, recording where and why it failed
+fn read_section<R: Read, T>(
+    reader: &mut OffsetReader<R>,
+    diagnostics: &mut Diagnostics,
+    section: &str,
+    read: impl FnOnce(&mut OffsetReader<R>) -> Result<T, MapLoadError>,
+) -> Result<T, MapLoadError> {
+    let start = reader.offset();
+    read(reader).map_err(|err| {
+        diagnostics.error(
+            section,
+            Some(reader.offset()),
+            format!("{} (section starts at {:#x})", err, start),
+        );
+        err
+    })
+}
+
+/// Loads a map stored in the native Outpost 2 `.map` layout
+///
+/// The file consists of a header, the packed 32-bit tile words, the clip rect,
+/// the tileset source table, the "TILE SET" tag, the tile mappings, the terrain
+/// types and (for maps only, not saved games) the tile groups. Saved games use
+/// the same layout with the unit table in place of the tile groups.
+fn load_op2_map_format<R: Read>(
+    mut reader: OffsetReader<R>,
+    diagnostics: &mut Diagnostics,
+) -> Result<Map, MapLoadError> {
+    let reader = &mut reader;
+    let header = read_section(reader, diagnostics, "header", read_map_header)?;
+    let width = 1u32 << header.lg_width;
+    let height = header.height;
 
-    // Create map info
     let info = MapInfo {
         width,
         height,
-        name: "Sample Map".to_string(),
         description: format!("Map size: {}x{}", width, height),
-        author: String::new(),
-        requirements: Vec::new(),
+        version_tag: header.version_tag,
+        is_saved_game: header.is_saved_game,
+        ..Default::default()
     };
-
-    // Create our map structure
     let mut map = Map::new(info);
 
-    // Skip some header data
-    println!("Skipping to cell data section at offset 32");
-    reader.seek(SeekFrom::Start(32))?;
+    let tiles = read_section(reader, diagnostics, "tile data", |r| {
+        read_tile_words(r, width, height)
+    })?;
+
+    map.clip_rect = read_section(reader, diagnostics, "clip rect", |r| {
+        Ok(ClipRect {
+            x1: read_i32(r)?,
+            y1: read_i32(r)?,
+            x2: read_i32(r)?,
+            y2: read_i32(r)?,
+        })
+    })?;
+
+    let sources_offset = reader.offset();
+    map.tileset_sources = read_section(reader, diagnostics, "tileset sources", |r| {
+        (0..header.tileset_count)
+            .map(|_| read_tileset_source(r))
+            .collect()
+    })?;
+    let used_sources = map
+        .tileset_sources
+        .iter()
+        .filter(|source| !source.filename.is_empty())
+        .count();
+    diagnostics.info(
+        "tileset sources",
+        Some(sources_offset),
+        format!(
+            "{} of {} tileset slots in use",
+            used_sources, header.tileset_count
+        ),
+    );
 
-    // Read cell data
-    for y in 0..height as i32 {
-        for x in 0..width as i32 {
-            // Read 4 bytes for each cell
-            let mut cell_data = [0u8; 4];
-            match reader.read_exact(&mut cell_data) {
-                Ok(_) => {}
-                Err(e) => {
-                    println!(
-                        "ERROR: Failed to read cell data at position ({}, {}): {:?}",
-                        x, y, e
-                    );
-                    return Err(MapLoadError::IoError(e));
+    read_section(reader, diagnostics, "tileset tag", |r| {
+        let mut tag = [0u8; 10];
+        r.read_exact(&mut tag)?;
+        if &tag != TILESET_TAG {
+            return Err(MapLoadError::InvalidFormat(
+                "Missing \"TILE SET\" tag after tileset sources".into(),
+            ));
+        }
+        Ok(())
+    })?;
+
+    let mappings_offset = reader.offset();
+    map.tile_mappings = read_section(reader, diagnostics, "tile mappings", |r| {
+        let mapping_count = read_u32(r)?;
+        if mapping_count > MAX_TILE_MAPPINGS {
+            return Err(MapLoadError::InvalidFormat(format!(
+                "Too many tile mappings: {}",
+                mapping_count
+            )));
+        }
+        (0..mapping_count).map(|_| read_tile_mapping(r)).collect()
+    })?;
+    diagnostics.info(
+        "tile mappings",
+        Some(mappings_offset),
+        format!("{} tile mappings", map.tile_mappings.len()),
+    );
+
+    let terrain_offset = reader.offset();
+    map.terrain_types = read_section(reader, diagnostics, "terrain types", |r| {
+        let terrain_count = read_u32(r)?;
+        if terrain_count > MAX_TILE_MAPPINGS {
+            return Err(MapLoadError::InvalidFormat(format!(
+                "Too many terrain types: {}",
+                terrain_count
+            )));
+        }
+        (0..terrain_count).map(|_| read_terrain_type(r)).collect()
+    })?;
+    diagnostics.info(
+        "terrain types",
+        Some(terrain_offset),
+        format!("{} terrain types", map.terrain_types.len()),
+    );
+
+    // Saved games continue with unit and player data instead of tile groups
+    let mut unit_records = Vec::new();
+    if header.is_saved_game {
+        // The unit table is only partly understood, so a failure here keeps
+        // the terrain and leaves the units without their records
+        let units_offset = reader.offset();
+        match read_unit_table(reader, header.version_tag) {
+            Ok(records) => {
+                diagnostics.info(
+                    "unit table",
+                    Some(units_offset),
+                    format!("{} unit records", records.len()),
+                );
+                unit_records = records;
+            }
+            Err(err) => diagnostics.warning(
+                "unit table",
+                Some(reader.offset()),
+                format!("Could not read unit table: {}", err),
+            ),
+        }
+    } else {
+        read_section(reader, diagnostics, "version tags", |r| {
+            for _ in 0..2 {
+                let tag = read_u32(r)?;
+                if tag != header.version_tag {
+                    return Err(MapLoadError::InvalidFormat(format!(
+                        "Expected version tag {:#x} before tile groups, found {:#x}",
+                        header.version_tag, tag
+                    )));
                 }
             }
+            Ok(())
+        })?;
+        let groups_offset = reader.offset();
+        (map.tile_groups, map.tile_groups_unknown) =
+            read_section(reader, diagnostics, "tile groups", read_tile_groups)?;
+        diagnostics.info(
+            "tile groups",
+            Some(groups_offset),
+            format!("{} tile groups", map.tile_groups.len()),
+        );
+    }
 
-            if x == 0 && y == 0 {
-                println!("First cell data: {:?}", cell_data);
+    for y in 0..height {
+        for x in 0..width {
+            if let Some(tile) = map.tile_mut(x as i32, y as i32) {
+                *tile = TileWord(tiles[tile_word_index(x, y, height)]);
             }
+        }
+    }
+    check_tile_references(&map, diagnostics);
+    map.units = collect_units(&map, unit_records);
 
-            let cell_type = determine_cell_type(&cell_data);
-            let tile_info = determine_tile_info(&cell_data);
+    Ok(map)
+}
 
-            let mut cell = Cell::new(
-                Position::new(x, y),
-                cell_type,
-                cell_data[2], // Height
-            );
+/// Reads and validates the map header
+fn read_map_header<R: Read>(reader: &mut R) -> Result<MapHeader, MapLoadError> {
+    let header = MapHeader {
+        version_tag: read_u32(reader)?,
+        is_saved_game: read_u32(reader)? != 0,
+        lg_width: read_u32(reader)?,
+        height: read_u32(reader)?,
+        tileset_count: read_u32(reader)?,
+    };
+
+    if header.version_tag < MAP_VERSION_TAG {
+        return Err(MapLoadError::UnsupportedVersion(header.version_tag));
+    }
+    if !LG_WIDTH_RANGE.contains(&header.lg_width)
+        || header.height == 0
+        || header.height > MAX_MAP_DIMENSION
+    {
+        return Err(MapLoadError::InvalidFormat(format!(
+            "Invalid map dimensions: lgWidth {}, height {}",
+            header.lg_width, header.height
+        )));
+    }
 
-            // Set additional properties
-            cell.has_wreckage = (cell_data[3] & 1) != 0;
-            cell.has_unit = (cell_data[3] & 2) != 0;
-            cell.tile_info = Some(tile_info);
+    Ok(header)
+}
 
-            if let Some(cell_ref) = map.get_cell_mut(x, y) {
-                *cell_ref = cell;
-            }
+/// Reads the tile words in file order (vertical strips of 32 columns)
+fn read_tile_words<R: Read>(
+    reader: &mut R,
+    width: u32,
+    height: u32,
+) -> Result<Vec<u32>, MapLoadError> {
+    let mut bytes = vec![0u8; width as usize * height as usize * 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect())
+}
+
+/// Converts tile coordinates into an index into the on-disk tile array
+pub(super) fn tile_word_index(x: u32, y: u32, height: u32) -> usize {
+    let block = x / TILE_COLUMN_BLOCK;
+    let column = x % TILE_COLUMN_BLOCK;
+    (block * height * TILE_COLUMN_BLOCK + y * TILE_COLUMN_BLOCK + column) as usize
+}
+
+fn read_tileset_source<R: Read>(reader: &mut R) -> Result<TilesetSource, MapLoadError> {
+    let filename = read_sized_string(reader)?;
+    if filename.len() > 8 {
+        return Err(MapLoadError::InvalidFormat(format!(
+            "Tileset filename too long: {:?}",
+            String::from_utf8_lossy(&filename)
+        )));
+    }
+
+    // Empty slots don't store a tile count
+    let num_tiles = if filename.is_empty() {
+        0
+    } else {
+        read_u32(reader)?
+    };
+
+    Ok(TilesetSource {
+        filename: String::from_utf8_lossy(&filename).into_owned(),
+        num_tiles,
+    })
+}
+
+fn read_tile_mapping<R: Read>(reader: &mut R) -> Result<TileMapping, MapLoadError> {
+    Ok(TileMapping {
+        tileset_index: read_u16(reader)?,
+        tile_graphic_index: read_u16(reader)?,
+        animation_count: read_u16(reader)?,
+        animation_delay: read_u16(reader)?,
+    })
+}
+
+fn read_mapping_range<R: Read>(reader: &mut R) -> Result<MappingRange, MapLoadError> {
+    Ok(MappingRange {
+        start: read_u16(reader)?,
+        end: read_u16(reader)?,
+    })
+}
+
+fn read_terrain_type<R: Read>(reader: &mut R) -> Result<TerrainType, MapLoadError> {
+    let mut terrain = TerrainType {
+        tile_mapping_range: read_mapping_range(reader)?,
+        bulldozed_mapping: read_u16(reader)?,
+        rubble_mapping: read_u16(reader)?,
+        ..Default::default()
+    };
+    read_u16_array(reader, &mut terrain.tube_mappings)?;
+    for wall in terrain.wall_mappings.iter_mut() {
+        read_u16_array(reader, wall)?;
+    }
+    terrain.lava_mapping = read_u16(reader)?;
+    read_u16_array(reader, &mut terrain.flat)?;
+    read_u16_array(reader, &mut terrain.tube_connection_mappings)?;
+    terrain.scorched_mapping = read_u16(reader)?;
+    for range in terrain.scorched_ranges.iter_mut() {
+        *range = read_mapping_range(reader)?;
+    }
+    read_u16_array(reader, &mut terrain.unknown)?;
+    Ok(terrain)
+}
+
+fn read_tile_groups<R: Read>(reader: &mut R) -> Result<(Vec<TileGroup>, u32), MapLoadError> {
+    let group_count = read_u32(reader)?;
+    // Unknown field, kept so the map can be written back unchanged
+    let unknown = read_u32(reader)?;
+
+    let mut groups = Vec::new();
+    for _ in 0..group_count {
+        let width = read_u32(reader)?;
+        let height = read_u32(reader)?;
+        let max_size = 1 << LG_WIDTH_RANGE.end();
+        if width > max_size || height > max_size {
+            return Err(MapLoadError::InvalidFormat(format!(
+                "Invalid tile group size: {}x{}",
+                width, height
+            )));
+        }
+        let mapping_indices = (0..width * height)
+            .map(|_| read_u32(reader))
+            .collect::<io::Result<_>>()?;
+        let name = read_sized_string(reader)?;
+        groups.push(TileGroup {
+            width,
+            height,
+            mapping_indices,
+            name,
+        });
+    }
+    Ok((groups, unknown))
+}
+
+/// Reports tiles and tile mappings that point at data the map doesn't have
+fn check_tile_references(map: &Map, diagnostics: &mut Diagnostics) {
+    let missing_mappings = map
+        .cells()
+        .filter(|cell| cell.tile.tile_mapping_index() as usize >= map.tile_mappings.len())
+        .count();
+    if missing_mappings > 0 {
+        diagnostics.warning(
+            "tile data",
+            None,
+            format!(
+                "{} tiles use a mapping index beyond the {} tile mappings",
+                missing_mappings,
+                map.tile_mappings.len()
+            ),
+        );
+    }
+
+    let mut unused_slots = map
+        .tile_mappings
+        .iter()
+        .enumerate()
+        .filter(|(_, mapping)| map.tileset_name(mapping.tileset_index).is_none());
+    if let Some((first, mapping)) = unused_slots.next() {
+        diagnostics.warning(
+            "tile mappings",
+            None,
+            format!(
+                "{} tile mappings refer to unused tileset slots (first: mapping {}, slot {})",
+                unused_slots.count() + 1,
+                first,
+                mapping.tileset_index
+            ),
+        );
+    }
+}
+
+/// Reads the unit table that follows the terrain types in saved games
+///
+/// The table starts with the version tag and five counters: the number of
+/// records, the last used index, the next and first free slots, and the size
+/// of a record. Two object arrays follow, counted by a pair of `u32`s: one of
+/// 512-byte entries and one of `u32`s, then the indices of the first and last
+/// units in the game's unit list. The records come last; they are the game's
+/// in-memory unit structures and are kept as raw bytes.
+fn read_unit_table<R: Read>(
+    reader: &mut R,
+    version_tag: u32,
+) -> Result<Vec<Vec<u8>>, MapLoadError> {
+    let tag = read_u32(reader)?;
+    if tag != version_tag {
+        return Err(MapLoadError::InvalidFormat(format!(
+            "Expected version tag {:#x} before unit table, found {:#x}",
+            version_tag, tag
+        )));
+    }
+
+    let record_count = read_u32(reader)?;
+    let _last_used_index = read_u32(reader)?;
+    let _next_free_index = read_u32(reader)?;
+    let _first_free_index = read_u32(reader)?;
+    let record_size = read_u32(reader)?;
+    if record_count > MAX_UNITS || record_size == 0 || record_size > MAX_UNIT_RECORD_SIZE {
+        return Err(MapLoadError::InvalidFormat(format!(
+            "Implausible unit table: {} records of {} bytes",
+            record_count, record_size
+        )));
+    }
+
+    let object_count = read_u32(reader)?;
+    let index_count = read_u32(reader)?;
+    if object_count > MAX_UNITS || index_count > MAX_UNITS {
+        return Err(MapLoadError::InvalidFormat(format!(
+            "Implausible unit table: object arrays of {} and {} entries",
+            object_count, index_count
+        )));
+    }
+    let skipped = object_count as u64 * UNIT_TABLE_OBJECT_SIZE as u64 + index_count as u64 * 4;
+    if io::copy(&mut reader.by_ref().take(skipped), &mut io::sink())? != skipped {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    let _first_unit_index = read_u32(reader)?;
+    let _last_unit_index = read_u32(reader)?;
+
+    (0..record_count)
+        .map(|_| {
+            let mut record = vec![0u8; record_size as usize];
+            reader.read_exact(&mut record)?;
+            Ok(record)
+        })
+        .collect()
+}
+
+/// Gathers units from the unit indices stored in the tile words
+fn collect_units(map: &Map, mut records: Vec<Vec<u8>>) -> Vec<Unit> {
+    let mut units: BTreeMap<u16, Unit> = BTreeMap::new();
+    for cell in map.cells() {
+        let index = cell.tile.unit_index();
+        if index == 0 {
+            continue;
         }
+        let position = cell.position;
+        units
+            .entry(index)
+            .and_modify(|unit| {
+                unit.min.x = unit.min.x.min(position.x);
+                unit.min.y = unit.min.y.min(position.y);
+                unit.max.x = unit.max.x.max(position.x);
+                unit.max.y = unit.max.y.max(position.y);
+            })
+            .or_insert_with(|| Unit {
+                index,
+                min: position,
+                max: position,
+                record: None,
+            });
     }
 
-    Ok(map)
+    for unit in units.values_mut() {
+        if let Some(record) = records.get_mut(unit.index as usize) {
+            unit.record = Some(std::mem::take(record));
+        }
+    }
+    units.into_values().collect()
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_le_bytes(buf))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> io::Result<i32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+fn read_u16_array<R: Read>(reader: &mut R, values: &mut [u16]) -> io::Result<()> {
+    for value in values.iter_mut() {
+        *value = read_u16(reader)?;
+    }
+    Ok(())
+}
+
+/// Reads a string prefixed by its 32-bit length
+fn read_sized_string<R: Read>(reader: &mut R) -> Result<Vec<u8>, MapLoadError> {
+    let len = read_u32(reader)?;
+    if len > 0xFFFF {
+        return Err(MapLoadError::InvalidFormat(format!(
+            "Implausible string length: {}",
+            len
+        )));
+    }
+    let mut bytes = vec![0u8; len as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
 }
 
 /// Loads a map in FORM2 format
-fn load_form2_map<R: Read + Seek>(mut reader: R) -> Result<Map, MapLoadError> {
+fn load_form2_map<R: Read + Seek>(
+    mut reader: R,
+    diagnostics: &mut Diagnostics,
+) -> Result<Map, MapLoadError> {
     let mut header = [0u8; 8];
     reader.read_exact(&mut header)?;
 
     // Check magic number "FORM2" and version
     if &header[0..5] != b"FORM2" {
-        println!("ERROR: Not a FORM2 map file. Header: {:?}", &header[0..5]);
+        diagnostics.error(
+            "FORM2 header",
+            Some(0),
+            format!("Not a FORM2 map file. Header: {:?}", &header[0..5]),
+        );
         return Err(MapLoadError::InvalidFormat("Not a FORM2 map file".into()));
     }
 
     let version = u16::from_le_bytes([header[6], header[7]]);
-    println!("FORM2 map version: {}", version);
+    diagnostics.info(
+        "FORM2 header",
+        Some(6),
+        format!("FORM2 map version: {}", version),
+    );
     if version != 1 {
-        println!("ERROR: Unsupported FORM2 map version: {}", version);
+        diagnostics.error(
+            "FORM2 header",
+            Some(6),
+            format!("Unsupported FORM2 map version: {}", version),
+        );
         return Err(MapLoadError::UnsupportedVersion(version as u32));
     }
 
@@ -195,6 +771,11 @@ fn load_form2_map<R: Read + Seek>(mut reader: R) -> Result<Map, MapLoadError> {
     reader.read_exact(&mut dim)?;
     let width = u32::from_le_bytes([dim[0], dim[1], dim[2], dim[3]]);
     let height = u32::from_le_bytes([dim[4], dim[5], dim[6], dim[7]]);
+    if !(1..=MAX_MAP_DIMENSION).contains(&width) || !(1..=MAX_MAP_DIMENSION).contains(&height) {
+        let message = format!("Invalid FORM2 map size {}x{}", width, height);
+        diagnostics.error("FORM2 header", Some(8), message.clone());
+        return Err(MapLoadError::InvalidFormat(message));
+    }
 
     // Create map info
     let mut info = MapInfo {
@@ -226,36 +807,25 @@ fn load_form2_map<R: Read + Seek>(mut reader: R) -> Result<Map, MapLoadError> {
             let mut cell_data = [0u8; 4];
             reader.read_exact(&mut cell_data)?;
 
-            let cell_type = match cell_data[0] {
-                0 => CellType::Normal,
-                1 => CellType::Dirt(cell_data[1]),
-                2 => CellType::Lava(cell_data[1]),
-                3 => CellType::Microbe(cell_data[1]),
-                4 => CellType::Mine(cell_data[1] != 0),
-                5 => CellType::Rock(cell_data[1]),
-                6 => CellType::Tube(cell_data[1]),
-                7 => CellType::Wall(cell_data[1]),
-                n => {
-                    return Err(MapLoadError::InvalidFormat(format!(
-                        "Invalid cell type: {}",
-                        n
-                    )))
-                }
+            let Some(kind) = Form2Kind::from_code(cell_data[0]) else {
+                let message = format!("Invalid FORM2 cell code {} at ({}, {})", cell_data[0], x, y);
+                diagnostics.error(
+                    "FORM2 cells",
+                    Some(reader.stream_position()? - cell_data.len() as u64),
+                    message.clone(),
+                );
+                return Err(MapLoadError::InvalidFormat(message));
             };
 
+            let variant = cell_data[1];
             let height = cell_data[2];
             let flags = cell_data[3];
 
-            let mut cell = Cell::new(Position::new(x, y), cell_type, height);
-            cell.has_wreckage = (flags & 1) != 0;
-            cell.has_unit = (flags & 2) != 0;
-
-            // Add tile info based on cell type
-            cell.tile_info = Some(get_tile_info_for_cell_type(&cell_type));
-
-            if let Some(cell_ref) = map.get_cell_mut(x, y) {
-                *cell_ref = cell;
+            if let Some(tile) = map.tile_mut(x, y) {
+                *tile = kind.to_tile_word(variant);
             }
+            map.set_cell_details(x, y, height, (flags & 1) != 0, (flags & 2) != 0);
+            map.set_form2_cell(x, y, Form2Cell { kind, variant });
         }
     }
 
@@ -263,229 +833,85 @@ fn load_form2_map<R: Read + Seek>(mut reader: R) -> Result<Map, MapLoadError> {
 }
 
 /// Converts an op2utility_rs map to our map format
-fn convert_op2_map(op2_map: Op2Map, file_path: &Path) -> Result<Map, MapLoadError> {
+///
+/// The library only exposes the map dimensions; its cells keep the tile word
+/// private, so the cell types, tile mappings, lava and microbe flags and the
+/// tileset list can't be read. Rather than invent cell contents the conversion
+/// fails, leaving the other formats' errors to explain the problem.
+fn convert_op2_map(op2_map: Op2Map) -> Result<Map, MapLoadError> {
     let (width, height) = op2_map.dimensions();
+    Err(MapLoadError::InvalidFormat(format!(
+        "op2utility_rs read a {}x{} map but does not expose its tile data",
+        width, height
+    )))
+}
 
-    // Extract file name from path
-    let map_name = file_path
-        .file_stem()
-        .and_then(|s| s.to_str())
-        .unwrap_or("Unnamed Map")
-        .to_string();
-
-    println!("Converting op2utility_rs map: {}", map_name);
-    println!("Map dimensions: {}x{}", width, height);
-
-    // Create map info
-    let info = MapInfo {
-        width,
-        height,
-        name: map_name,
-        description: format!("Map size: {}x{}", width, height),
-        author: String::new(),
-        requirements: Vec::new(),
-    };
-
-    // Create our map structure
-    let mut map = Map::new(info);
-
-    // Convert all cells
-    for y in 0..height as i32 {
-        for x in 0..width as i32 {
-            if let Ok(_) = op2_map.get_cell(x as u32, y as u32) {
-                // Since we can't directly access the private fields, we'll determine the cell type
-                // based on position and map properties
-                let (cell_type, tile_info) = determine_cell_properties(x as u32, y as u32);
-
-                let mut cell = Cell::new(
-                    Position::new(x, y),
-                    cell_type,
-                    0, // Default height
-                );
-
-                // Set additional properties
-                cell.has_wreckage = false;
-                cell.has_unit = false;
-                cell.tile_info = Some(tile_info);
+/// Loads tileset images from the provided zip file, VOL archive or directory
+///
+/// Both standard images and the game's own PBMP tilesets are accepted.
+/// Files that can't be decoded are skipped and reported in `diagnostics`.
+pub fn load_tilesets(
+    tileset_path: &Path,
+    diagnostics: &mut Diagnostics,
+) -> Result<Arc<TilesetCache>, MapLoadError> {
+    let mut tileset_cache = TilesetCache::new();
+    diagnostics.info(
+        "tilesets",
+        None,
+        format!("Loading tilesets from {}", tileset_path.display()),
+    );
 
-                if let Some(cell_ref) = map.get_cell_mut(x, y) {
-                    *cell_ref = cell;
+    if vol::is_vol_file(tileset_path) {
+        let archive = VolArchive::open(tileset_path)?;
+        for entry in archive.entries() {
+            let Some(name) = strip_bmp_extension(&entry.name) else {
+                continue;
+            };
+            match archive.read_entry(entry) {
+                Ok(buffer) => {
+                    let source = format!("{}:{}", tileset_path.display(), entry.name);
+                    add_tileset_data(
+                        &mut tileset_cache,
+                        name.to_string(),
+                        source,
+                        &buffer,
+                        diagnostics,
+                    )
                 }
+                Err(e) => diagnostics.warning(
+                    entry.name.as_str(),
+                    Some(entry.offset as u64),
+                    format!("Failed to read: {}", e),
+                ),
             }
         }
+        report_tileset_count(&tileset_cache, diagnostics);
+        return Ok(Arc::new(tileset_cache));
     }
 
-    Ok(map)
-}
+    if tileset_path.is_dir() {
+        for entry in std::fs::read_dir(tileset_path)? {
+            let path = entry?.path();
+            let is_bmp = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("bmp"));
+            if !is_bmp {
+                continue;
+            }
 
-/// Determines cell type from raw cell data
-fn determine_cell_type(cell_data: &[u8; 4]) -> CellType {
-    // The first byte typically indicates the cell type
-    match cell_data[0] % 8 {
-        0 => CellType::Normal,
-        1 => CellType::Dirt(cell_data[1] % 3),
-        2 => CellType::Lava(cell_data[1] % 3),
-        3 => CellType::Microbe(cell_data[1] % 3),
-        4 => CellType::Mine(cell_data[1] != 0),
-        5 => CellType::Rock(cell_data[1] % 3),
-        6 => CellType::Tube(cell_data[1]),
-        7 => CellType::Wall(cell_data[1] % 3),
-        _ => CellType::Normal, // Fallback
-    }
-}
-
-/// Determines tile info from raw cell data
-fn determine_tile_info(cell_data: &[u8; 4]) -> TileInfo {
-    match cell_data[0] % 8 {
-        0 => TileInfo {
-            tileset_name: "well0005".to_string(), // Normal
-            tile_index: 0,
-        },
-        1 => TileInfo {
-            tileset_name: "well0002".to_string(), // Dirt
-            tile_index: cell_data[1] as u32 % 3,
-        },
-        2 => TileInfo {
-            tileset_name: "well0004".to_string(), // Lava
-            tile_index: cell_data[1] as u32 % 3,
-        },
-        3 => TileInfo {
-            tileset_name: "well0003".to_string(), // Microbe
-            tile_index: cell_data[1] as u32 % 3,
-        },
-        4 => TileInfo {
-            tileset_name: "well0000".to_string(), // Mine
-            tile_index: if cell_data[1] != 0 { 1 } else { 0 },
-        },
-        5 => TileInfo {
-            tileset_name: "well0001".to_string(), // Rock
-            tile_index: cell_data[1] as u32 % 3,
-        },
-        6 => TileInfo {
-            tileset_name: "well0012".to_string(), // Tube
-            tile_index: cell_data[1] as u32 % 4,
-        },
-        7 => TileInfo {
-            tileset_name: "well0005".to_string(),    // Wall
-            tile_index: cell_data[1] as u32 % 3 + 1, // Start from 1 to be different from normal
-        },
-        _ => TileInfo {
-            tileset_name: "well0005".to_string(), // Fallback
-            tile_index: 0,
-        },
-    }
-}
-
-/// Gets tile information for a given cell type
-fn get_tile_info_for_cell_type(cell_type: &CellType) -> TileInfo {
-    match cell_type {
-        CellType::Normal => TileInfo {
-            tileset_name: "well0005".to_string(),
-            tile_index: 0,
-        },
-        CellType::Dirt(variant) => TileInfo {
-            tileset_name: "well0002".to_string(),
-            tile_index: *variant as u32 % 3,
-        },
-        CellType::Lava(variant) => TileInfo {
-            tileset_name: "well0004".to_string(),
-            tile_index: *variant as u32 % 3,
-        },
-        CellType::Microbe(variant) => TileInfo {
-            tileset_name: "well0003".to_string(),
-            tile_index: *variant as u32 % 3,
-        },
-        CellType::Mine(depleted) => TileInfo {
-            tileset_name: "well0000".to_string(),
-            tile_index: if *depleted { 1 } else { 0 },
-        },
-        CellType::Rock(variant) => TileInfo {
-            tileset_name: "well0001".to_string(),
-            tile_index: *variant as u32 % 3,
-        },
-        CellType::Tube(connections) => TileInfo {
-            tileset_name: "well0012".to_string(),
-            tile_index: *connections as u32 % 4,
-        },
-        CellType::Wall(variant) => TileInfo {
-            tileset_name: "well0005".to_string(),
-            tile_index: *variant as u32 % 3 + 1,
-        },
-    }
-}
-
-/// Determines cell properties based on position
-fn determine_cell_properties(x: u32, y: u32) -> (CellType, TileInfo) {
-    // Create a simple pattern based on coordinates
-    let pattern = (x + y) % 8;
-
-    match pattern {
-        0 => (
-            CellType::Rock(0),
-            TileInfo {
-                tileset_name: "well0001".to_string(),
-                tile_index: 0,
-            },
-        ),
-        1 => (
-            CellType::Dirt(1),
-            TileInfo {
-                tileset_name: "well0002".to_string(),
-                tile_index: 1,
-            },
-        ),
-        2 => (
-            CellType::Lava(2),
-            TileInfo {
-                tileset_name: "well0004".to_string(),
-                tile_index: 2,
-            },
-        ),
-        3 => (
-            CellType::Microbe(1),
-            TileInfo {
-                tileset_name: "well0003".to_string(),
-                tile_index: 1,
-            },
-        ),
-        4 => (
-            CellType::Mine(false),
-            TileInfo {
-                tileset_name: "well0000".to_string(),
-                tile_index: 0,
-            },
-        ),
-        5 => (
-            CellType::Tube(0),
-            TileInfo {
-                tileset_name: "well0012".to_string(),
-                tile_index: 0,
-            },
-        ),
-        6 => (
-            CellType::Wall(0),
-            TileInfo {
-                tileset_name: "well0005".to_string(),
-                tile_index: 1,
-            },
-        ),
-        _ => (
-            CellType::Normal,
-            TileInfo {
-                tileset_name: "well0005".to_string(),
-                tile_index: 0,
-            },
-        ),
+            let name = map_name_from_path(&path);
+            let buffer = std::fs::read(&path)?;
+            let source = path.display().to_string();
+            add_tileset_data(&mut tileset_cache, name, source, &buffer, diagnostics);
+        }
+        report_tileset_count(&tileset_cache, diagnostics);
+        return Ok(Arc::new(tileset_cache));
     }
-}
 
-/// Loads tileset images from the provided zip file
-pub fn load_tilesets(tileset_path: &Path) -> Result<Arc<TilesetCache>, MapLoadError> {
     let file = File::open(tileset_path)?;
     let mut archive = ZipArchive::new(file)?;
 
-    let mut tileset_cache = TilesetCache::new();
-
     // Extract and load tileset images
     for i in 0..archive.len() {
         let mut file = archive.by_index(i)?;
@@ -498,48 +924,601 @@ pub fn load_tilesets(tileset_path: &Path) -> Result<Arc<TilesetCache>, MapLoadEr
         let mut buffer = Vec::new();
         file.read_to_end(&mut buffer)?;
 
-        // Load the image data - BMP files from OP2 might need special handling
-        let image = match image::load_from_memory(&buffer) {
-            Ok(img) => img,
-            Err(e) => {
-                // Try to load as a BMP specifically with different options
-                match image::load_from_memory_with_format(&buffer, image::ImageFormat::Bmp) {
-                    Ok(img) => img,
-                    Err(_) => {
-                        println!("Warning: Failed to load image {}: {}", name, e);
-                        continue;
-                    }
-                }
-            }
+        let source = format!("{}:{}", tileset_path.display(), file.name());
+        add_tileset_data(&mut tileset_cache, name, source, &buffer, diagnostics);
+    }
+
+    report_tileset_count(&tileset_cache, diagnostics);
+    Ok(Arc::new(tileset_cache))
+}
+
+/// Returns the file name without its ".bmp" extension, or `None` for other files
+fn strip_bmp_extension(name: &str) -> Option<&str> {
+    let split = name.len().checked_sub(4)?;
+    name.get(split..)
+        .filter(|ext| ext.eq_ignore_ascii_case(".bmp"))
+        .map(|_| &name[..split])
+}
+
+/// Decodes a tileset file and adds it to the cache, skipping files that fail to decode
+fn add_tileset_data(
+    tileset_cache: &mut TilesetCache,
+    name: String,
+    source: String,
+    buffer: &[u8],
+    diagnostics: &mut Diagnostics,
+) {
+    match tileset::load_tileset_image(buffer) {
+        Ok(image) => tileset_cache.add_tileset_from(name, image, source),
+        Err(e) => diagnostics.warning(name, None, format!("Failed to load image: {}", e)),
+    }
+}
+
+fn report_tileset_count(tileset_cache: &TilesetCache, diagnostics: &mut Diagnostics) {
+    diagnostics.info(
+        "tilesets",
+        None,
+        format!("Loaded {} tilesets", tileset_cache.tilesets.len()),
+    );
+}
+
+/// How tiles are arranged inside a tileset image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileLayout {
+    /// One tile wide, tiles stacked top to bottom (the Outpost 2 layout)
+    Vertical,
+    /// One tile high, tiles placed left to right
+    Horizontal,
+}
+
+/// A tileset image split into square tiles
+#[derive(Debug)]
+pub struct Tileset {
+    image: image::DynamicImage,
+    tile_size: u32,
+    layout: TileLayout,
+    /// Where the image was loaded from, e.g. `tilesets.zip:well0001.bmp`
+    source: Option<String>,
+}
+
+impl Tileset {
+    /// Width and height of an Outpost 2 tile in pixels
+    pub const DEFAULT_TILE_SIZE: u32 = 32;
+
+    /// Wraps a tileset image using the standard 32x32 tile size
+    ///
+    /// Images exactly one tile high are treated as horizontal strips; everything
+    /// else is assumed to be a vertical strip like the game's tilesets.
+    pub fn new(image: image::DynamicImage) -> Self {
+        let tile_size = Self::DEFAULT_TILE_SIZE;
+        let layout = if image.height() == tile_size && image.width() > tile_size {
+            TileLayout::Horizontal
+        } else {
+            TileLayout::Vertical
         };
+        Self {
+            image,
+            tile_size,
+            layout,
+            source: None,
+        }
+    }
 
-        tileset_cache.add_tileset(name, image);
+    /// Records where the tileset was loaded from
+    pub fn with_source(mut self, source: String) -> Self {
+        self.source = Some(source);
+        self
     }
 
-    Ok(Arc::new(tileset_cache))
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn image(&self) -> &image::DynamicImage {
+        &self.image
+    }
+
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+
+    pub fn layout(&self) -> TileLayout {
+        self.layout
+    }
+
+    /// Number of whole tiles in the image
+    pub fn tile_count(&self) -> u32 {
+        match self.layout {
+            TileLayout::Vertical => self.image.height() / self.tile_size,
+            TileLayout::Horizontal => self.image.width() / self.tile_size,
+        }
+    }
+
+    /// Top-left pixel of a tile, or `None` if the index is out of range
+    pub fn tile_origin(&self, index: u32) -> Option<(u32, u32)> {
+        if index >= self.tile_count() {
+            return None;
+        }
+        Some(match self.layout {
+            TileLayout::Vertical => (0, index * self.tile_size),
+            TileLayout::Horizontal => (index * self.tile_size, 0),
+        })
+    }
+
+    /// Normalised texture coordinates of a tile as `(min, max)` corners
+    pub fn tile_uv(&self, index: u32) -> Option<([f32; 2], [f32; 2])> {
+        let (x, y) = self.tile_origin(index)?;
+        let width = self.image.width() as f32;
+        let height = self.image.height() as f32;
+        let size = self.tile_size as f32;
+        Some((
+            [x as f32 / width, y as f32 / height],
+            [(x as f32 + size) / width, (y as f32 + size) / height],
+        ))
+    }
+
+    /// Copies a single tile out of the tileset
+    pub fn tile_image(&self, index: u32) -> Option<image::DynamicImage> {
+        let (x, y) = self.tile_origin(index)?;
+        Some(self.image.crop_imm(x, y, self.tile_size, self.tile_size))
+    }
+
+    /// How many tiles fit along an image side of at most `max_side` pixels
+    pub fn tiles_per_page(&self, max_side: u32) -> u32 {
+        (max_side / self.tile_size).max(1)
+    }
+
+    /// Splits the tileset into consecutive pages no longer than `max_side` pixels
+    ///
+    /// Outpost 2 tilesets can be over 10000 pixels tall, which is more than
+    /// many GPUs accept for a single texture.
+    pub fn split_pages(&self, max_side: u32) -> Vec<Tileset> {
+        let per_page = self.tiles_per_page(max_side);
+        (0..self.tile_count())
+            .step_by(per_page as usize)
+            .map(|first| {
+                let count = per_page.min(self.tile_count() - first);
+                let (x, y) = self
+                    .tile_origin(first)
+                    .expect("page start is within the tileset");
+                let (width, height) = match self.layout {
+                    TileLayout::Vertical => (self.tile_size, count * self.tile_size),
+                    TileLayout::Horizontal => (count * self.tile_size, self.tile_size),
+                };
+                Tileset {
+                    image: self.image.crop_imm(x, y, width, height),
+                    tile_size: self.tile_size,
+                    layout: self.layout,
+                    source: self.source.clone(),
+                }
+            })
+            .collect()
+    }
 }
 
 /// Cache for tileset images
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct TilesetCache {
-    tilesets: std::collections::HashMap<String, image::DynamicImage>,
+    tilesets: std::collections::HashMap<String, Tileset>,
 }
 
 impl TilesetCache {
     /// Creates a new, empty tileset cache
     pub fn new() -> Self {
-        Self {
-            tilesets: std::collections::HashMap::new(),
-        }
+        Self::default()
     }
 
     /// Adds a tileset to the cache
     pub fn add_tileset(&mut self, name: String, image: image::DynamicImage) {
-        self.tilesets.insert(name, image);
+        self.tilesets.insert(name, Tileset::new(image));
+    }
+
+    /// Adds a tileset to the cache, recording the file it came from
+    pub fn add_tileset_from(&mut self, name: String, image: image::DynamicImage, source: String) {
+        self.tilesets
+            .insert(name, Tileset::new(image).with_source(source));
     }
 
     /// Gets a tileset by name
-    pub fn get_tileset(&self, name: &str) -> Option<&image::DynamicImage> {
+    pub fn get_tileset(&self, name: &str) -> Option<&Tileset> {
         self.tilesets.get(name)
     }
+
+    /// All tilesets with their names, sorted by name
+    pub fn tilesets(&self) -> Vec<(&str, &Tileset)> {
+        let mut tilesets: Vec<(&str, &Tileset)> = self
+            .tilesets
+            .iter()
+            .map(|(name, tileset)| (name.as_str(), tileset))
+            .collect();
+        tilesets.sort_by_key(|&(name, _)| name);
+        tilesets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::types::{CellType, Position, UNIT_RECORD_SIZE};
+    use crate::map::Severity;
+
+    /// Builds a native map file with no tilesets, mappings or tile groups
+    ///
+    /// `tile_at` gives the raw tile word for each cell.
+    fn native_map(lg_width: u32, height: u32, tile_at: impl Fn(u32, u32) -> u32) -> Vec<u8> {
+        let width = 1u32 << lg_width;
+        let mut data = Vec::new();
+        for value in [MAP_VERSION_TAG, 0, lg_width, height, 0] {
+            data.extend(value.to_le_bytes());
+        }
+        // Vertical strips of 32 columns, each strip stored row by row
+        for block in 0..width.div_ceil(TILE_COLUMN_BLOCK) {
+            for y in 0..height {
+                for column in 0..TILE_COLUMN_BLOCK {
+                    data.extend(tile_at(block * TILE_COLUMN_BLOCK + column, y).to_le_bytes());
+                }
+            }
+        }
+        data.extend([0u8; 16]); // clip rect
+        data.extend(TILESET_TAG);
+        for value in [0, 0, MAP_VERSION_TAG, MAP_VERSION_TAG, 0, 0] {
+            data.extend(value.to_le_bytes());
+        }
+        data
+    }
+
+    fn load(data: &[u8]) -> Result<Map, MapLoadError> {
+        NativeFormat.load(data, &mut Diagnostics::new())
+    }
+
+    #[test]
+    fn reads_32_column_blocks() {
+        let map = load(&native_map(6, 3, |x, y| y << 16 | x)).unwrap();
+        assert_eq!((map.info.width, map.info.height), (64, 3));
+        for y in 0..3 {
+            for x in 0..64 {
+                assert_eq!(map.tile(x, y), Some(TileWord((y as u32) << 16 | x as u32)));
+            }
+        }
+    }
+
+    #[test]
+    fn tile_word_index_crosses_blocks() {
+        assert_eq!(tile_word_index(31, 0, 3), 31);
+        assert_eq!(tile_word_index(0, 1, 3), 32);
+        assert_eq!(tile_word_index(32, 0, 3), 96);
+        assert_eq!(tile_word_index(33, 2, 3), 96 + 64 + 1);
+    }
+
+    #[test]
+    fn rejects_width_that_is_not_a_multiple_of_32() {
+        // lgWidth 4 would be 16 columns, less than one block
+        let mut data = native_map(5, 2, |_, _| 0);
+        data[8..12].copy_from_slice(&4u32.to_le_bytes());
+        let err = load(&data).unwrap_err();
+        assert!(
+            matches!(&err, MapLoadError::InvalidFormat(message) if message.contains("lgWidth 4")),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let data = native_map(5, 2, |_, _| 0);
+        for len in [0, 4, 12, 19] {
+            let err = load(&data[..len]).unwrap_err();
+            assert!(
+                matches!(&err, MapLoadError::IoError(e) if e.kind() == io::ErrorKind::UnexpectedEof),
+                "{} bytes: {}",
+                len,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_tile_data() {
+        let data = native_map(5, 2, |_, _| 0);
+        assert!(matches!(
+            load(&data[..20 + 100]),
+            Err(MapLoadError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_tileset_tag() {
+        let mut data = native_map(5, 2, |_, _| 0);
+        let tag_offset = 20 + 32 * 2 * 4 + 16;
+        assert_eq!(&data[tag_offset..tag_offset + 10], TILESET_TAG);
+        data[tag_offset] = b'X';
+        let err = load(&data).unwrap_err();
+        assert!(
+            matches!(&err, MapLoadError::InvalidFormat(message) if message.contains("TILE SET")),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn rejects_bad_version_tag() {
+        let mut data = native_map(5, 2, |_, _| 0);
+        data[0..4].copy_from_slice(&0x1000u32.to_le_bytes());
+        assert!(matches!(
+            load(&data),
+            Err(MapLoadError::UnsupportedVersion(0x1000))
+        ));
+    }
+
+    /// Replaces the empty tile group list at the end of a native map
+    fn with_tile_groups(mut data: Vec<u8>, unknown: u32, groups: &[TileGroup]) -> Vec<u8> {
+        data.truncate(data.len() - 8);
+        data.extend((groups.len() as u32).to_le_bytes());
+        data.extend(unknown.to_le_bytes());
+        for group in groups {
+            data.extend(group.width.to_le_bytes());
+            data.extend(group.height.to_le_bytes());
+            for index in &group.mapping_indices {
+                data.extend(index.to_le_bytes());
+            }
+            data.extend((group.name.len() as u32).to_le_bytes());
+            data.extend(&group.name);
+        }
+        data
+    }
+
+    #[test]
+    fn reads_tile_groups() {
+        let groups = [
+            TileGroup {
+                width: 3,
+                height: 2,
+                mapping_indices: vec![10, 11, 12, 20, 21, 22],
+                name: b"cliff".to_vec(),
+            },
+            TileGroup {
+                width: 1,
+                height: 1,
+                mapping_indices: vec![7],
+                name: Vec::new(),
+            },
+        ];
+        let data = with_tile_groups(native_map(5, 2, |_, _| 0), 0xDEAD, &groups);
+        let mut diagnostics = Diagnostics::new();
+        let map = NativeFormat.load(&data, &mut diagnostics).unwrap();
+        assert_eq!(map.tile_groups, groups);
+        assert_eq!(map.tile_groups_unknown, 0xDEAD);
+        assert!(diagnostics
+            .entries()
+            .iter()
+            .any(|entry| entry.section == "tile groups" && entry.message == "2 tile groups"));
+    }
+
+    #[test]
+    fn rejects_truncated_tile_groups() {
+        let group = TileGroup {
+            width: 2,
+            height: 2,
+            mapping_indices: vec![1, 2, 3, 4],
+            name: b"crater".to_vec(),
+        };
+        let data = with_tile_groups(native_map(5, 2, |_, _| 0), 0, &[group]);
+        // Cut into the mapping indices, then into the name
+        for len in [data.len() - 12, data.len() - 3] {
+            assert!(matches!(load(&data[..len]), Err(MapLoadError::IoError(_))));
+        }
+    }
+
+    /// Builds a saved game around a native map, with `unit_table` in place of the tile groups
+    fn saved_game(
+        lg_width: u32,
+        height: u32,
+        tile_at: impl Fn(u32, u32) -> u32,
+        unit_table: &[u8],
+    ) -> Vec<u8> {
+        let mut map = native_map(lg_width, height, tile_at);
+        map[4..8].copy_from_slice(&1u32.to_le_bytes());
+        // Drop the version tags and empty tile group list
+        map.truncate(map.len() - 16);
+
+        let mut data = vec![0u8; SAVED_GAME_HEADER_SIZE];
+        data.extend(map);
+        data.extend(unit_table);
+        data
+    }
+
+    /// A unit record with the given type and owner
+    fn unit_record(unit_type: u32, owner: u8) -> Vec<u8> {
+        let mut record = vec![0u8; UNIT_RECORD_SIZE];
+        record[0..4].copy_from_slice(&unit_type.to_le_bytes());
+        record[0x41] = 0x70 | owner;
+        record
+    }
+
+    #[test]
+    fn reads_saved_game_units() {
+        // A 2x2 command center for player 2 and a lynx for player 0
+        let unit_at = |x, y| match (x, y) {
+            (3..=4, 1..=2) => 1,
+            (40, 0) => 2,
+            _ => 0,
+        };
+        let mut table = Vec::new();
+        for value in [MAP_VERSION_TAG, 3, 2, 3, 3, UNIT_RECORD_SIZE as u32] {
+            table.extend(value.to_le_bytes());
+        }
+        // Object arrays that precede the records
+        table.extend(2u32.to_le_bytes());
+        table.extend(3u32.to_le_bytes());
+        table.extend([0xAA; 2 * UNIT_TABLE_OBJECT_SIZE]);
+        table.extend([0xBB; 3 * 4]);
+        table.extend(1u32.to_le_bytes());
+        table.extend(2u32.to_le_bytes());
+        table.extend(vec![0u8; UNIT_RECORD_SIZE]);
+        table.extend(unit_record(0x1C, 2));
+        table.extend(unit_record(0x05, 0));
+
+        let data = saved_game(6, 4, |x, y| unit_at(x, y) << 16, &table);
+        let map = load(&data).unwrap();
+        assert!(map.info.is_saved_game);
+        assert_eq!(map.units.len(), 2);
+
+        let building = &map.units[0];
+        assert_eq!(building.index, 1);
+        assert_eq!(
+            (building.min, building.max),
+            (Position::new(3, 1), Position::new(4, 2))
+        );
+        assert_eq!(building.unit_type(), Some(0x1C));
+        assert_eq!(building.owner(), Some(2));
+
+        let vehicle = &map.units[1];
+        assert_eq!(vehicle.index, 2);
+        assert_eq!(
+            (vehicle.min, vehicle.max),
+            (Position::new(40, 0), Position::new(40, 0))
+        );
+        assert_eq!(vehicle.unit_type(), Some(0x05));
+        assert_eq!(vehicle.owner(), Some(0));
+    }
+
+    #[test]
+    fn keeps_saved_game_units_without_records_when_the_table_is_short() {
+        let mut table = Vec::new();
+        for value in [MAP_VERSION_TAG, 2, 1, 2, 2, UNIT_RECORD_SIZE as u32, 1, 0] {
+            table.extend(value.to_le_bytes());
+        }
+        // The object array is cut off
+        table.extend([0u8; 100]);
+
+        let map = load(&saved_game(
+            5,
+            2,
+            |x, y| u32::from(x == 1 && y == 1) << 16,
+            &table,
+        ))
+        .unwrap();
+        assert_eq!(map.units.len(), 1);
+        assert_eq!(map.units[0].record, None);
+        assert_eq!(map.units[0].owner(), None);
+    }
+
+    #[test]
+    fn saved_games_have_no_tile_group_section() {
+        // The unit table follows the terrain types directly
+        let mut table = Vec::new();
+        for value in [
+            MAP_VERSION_TAG,
+            1,
+            0,
+            1,
+            1,
+            UNIT_RECORD_SIZE as u32,
+            0,
+            0,
+            0,
+            0,
+        ] {
+            table.extend(value.to_le_bytes());
+        }
+        table.extend(vec![0u8; UNIT_RECORD_SIZE]);
+        // Player data follows; it would be misread as a tile group list
+        for value in [MAP_VERSION_TAG, MAP_VERSION_TAG, 1, 0, u32::MAX] {
+            table.extend(value.to_le_bytes());
+        }
+
+        let mut diagnostics = Diagnostics::new();
+        let map = NativeFormat
+            .load(&saved_game(5, 2, |_, _| 0, &table), &mut diagnostics)
+            .unwrap();
+        assert!(map.tile_groups.is_empty());
+        assert!(diagnostics.entries().iter().any(|entry| {
+            entry.section == "unit table"
+                && entry.severity == Severity::Info
+                && entry.message == "1 unit records"
+        }));
+        let sections: Vec<&str> = diagnostics
+            .entries()
+            .iter()
+            .map(|entry| entry.section.as_str())
+            .collect();
+        assert!(!sections.contains(&"tile groups"), "{:?}", sections);
+        assert!(!sections.contains(&"version tags"), "{:?}", sections);
+    }
+
+    /// Builds a FORM2 map with the given cells as (code, variant, height, flags)
+    fn form2_map(width: u32, cells: &[[u8; 4]]) -> Vec<u8> {
+        let mut data = b"FORM2\0".to_vec();
+        data.extend(1u16.to_le_bytes());
+        data.extend(width.to_le_bytes());
+        data.extend((cells.len() as u32 / width).to_le_bytes());
+        data.push(0); // name
+        data.extend(0u16.to_le_bytes()); // description
+        for cell in cells {
+            data.extend(cell);
+        }
+        data
+    }
+
+    #[test]
+    fn maps_form2_cells_to_game_cell_types() {
+        let cells = [
+            [0, 0, 10, 0],
+            [1, 3, 0, 0],
+            [2, 1, 0, 0],
+            [3, 2, 0, 0],
+            [4, 1, 0, 1],
+            [5, 0, 0, 0],
+            [6, 5, 0, 0],
+            [7, 1, 0, 2],
+        ];
+        let map = Form2Format
+            .load(&form2_map(4, &cells), &mut Diagnostics::new())
+            .unwrap();
+
+        let cell = |x, y| map.get_cell(x, y).unwrap();
+        assert_eq!(cell(0, 0).cell_type(), CellType::FastPassible1);
+        assert_eq!(cell(0, 0).height, 10);
+        assert_eq!(cell(1, 0).cell_type(), CellType::DozedArea);
+        assert_eq!(cell(2, 0).cell_type(), CellType::FastPassible1);
+        assert!(cell(2, 0).tile.is_lava() && cell(2, 0).tile.is_lava_possible());
+        assert!(cell(3, 0).tile.is_microbe());
+        assert!(cell(0, 1).has_wreckage);
+        assert_eq!(cell(1, 1).cell_type(), CellType::Impassible1);
+        assert_eq!(cell(2, 1).cell_type(), CellType::Tube0);
+        assert_eq!(cell(3, 1).cell_type(), CellType::LavaWall);
+        assert!(cell(3, 1).tile.is_wall_or_building() && cell(3, 1).has_unit);
+
+        // The FORM2 kind and variant are kept alongside the translation
+        for (index, data) in cells.iter().enumerate() {
+            let form2 = cell(index as i32 % 4, index as i32 / 4).form2.unwrap();
+            assert_eq!(form2.kind, Form2Kind::from_code(data[0]).unwrap());
+            assert_eq!(form2.variant, data[1]);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_form2_cell_codes() {
+        let data = form2_map(1, &[[8, 0, 0, 0]]);
+        assert!(matches!(
+            Form2Format.load(&data, &mut Diagnostics::new()),
+            Err(MapLoadError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_oversized_form2_dimensions() {
+        for (width, height) in [(0, 1), (1, 0), (513, 1), (1, 513), (u32::MAX, u32::MAX)] {
+            let mut data = form2_map(1, &[[0, 0, 0, 0]]);
+            data[8..12].copy_from_slice(&width.to_le_bytes());
+            data[12..16].copy_from_slice(&height.to_le_bytes());
+
+            let mut diagnostics = Diagnostics::new();
+            assert!(matches!(
+                Form2Format.load(&data, &mut diagnostics),
+                Err(MapLoadError::InvalidFormat(_))
+            ));
+            assert_eq!(diagnostics.count(Severity::Error), 1);
+        }
+    }
 }
@@ -0,0 +1,366 @@
+//! Decompression of LZH-compressed VOL archive entries
+//!
+//! The game compresses archive entries with a variant of LZHUF: LZ77 over a
+//! 4096-byte window that starts out filled with spaces, where literals and match lengths share one adaptive
+//! Huffman code and the upper six bits of each match offset use a fixed code.
+//! Bits are read most significant first. The stream doesn't store its
+//! uncompressed size, so decoding stops when the input runs out.
+
+/// Longest match
+const MAX_MATCH: usize = 60;
+
+/// Matches this long or shorter are stored as literals
+const THRESHOLD: usize = 2;
+
+/// Number of codes: one per literal byte and one per match length
+const CODE_COUNT: usize = 256 - THRESHOLD + MAX_MATCH;
+
+/// Number of nodes in the Huffman tree
+const NODE_COUNT: usize = CODE_COUNT * 2 - 1;
+
+const ROOT: usize = NODE_COUNT - 1;
+
+/// The tree is rebuilt with halved frequencies when the root reaches this
+const MAX_FREQUENCY: u32 = 0x8000;
+
+/// Decompresses an LZH stream
+pub fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitReader { data, position: 0 };
+    let mut tree = HuffmanTree::new();
+    let mut output = Vec::with_capacity(data.len() * 2);
+
+    while let Some(code) = tree.decode(&mut bits) {
+        if code < 256 {
+            output.push(code as u8);
+            continue;
+        }
+
+        let Some(offset) = decode_offset(&mut bits) else {
+            break;
+        };
+        let length = code - 256 + THRESHOLD + 1;
+        for _ in 0..length {
+            // Bytes before the start of the output are the window's spaces
+            let byte = output
+                .len()
+                .checked_sub(offset + 1)
+                .map_or(b' ', |at| output[at]);
+            output.push(byte);
+        }
+    }
+
+    output
+}
+
+/// Reads a match offset: the first byte read gives the upper six bits and
+/// how many more bits to read for the lower six
+fn decode_offset(bits: &mut BitReader) -> Option<usize> {
+    let mut value = bits.read_bits(8)? as usize;
+    let (upper, prefix_len) = offset_prefix(value as u8);
+    for _ in 0..prefix_len - 2 {
+        value = (value << 1) | bits.read_bit()? as usize;
+    }
+    Some((upper << 6) | (value & 0x3F))
+}
+
+/// Upper six offset bits and the length of their code, for the first byte of
+/// an encoded offset
+///
+/// Shorter codes go to the smaller upper values, as nearby matches are the
+/// most common.
+fn offset_prefix(byte: u8) -> (usize, u32) {
+    // How many upper values share each code length
+    const GROUPS: [(usize, u32); 6] = [(1, 3), (3, 4), (8, 5), (12, 6), (24, 7), (16, 8)];
+
+    let byte = byte as usize;
+    let mut first_byte = 0;
+    let mut upper = 0;
+    for (count, prefix_len) in GROUPS {
+        let bytes_per_value = 1 << (8 - prefix_len);
+        if byte < first_byte + count * bytes_per_value {
+            return (upper + (byte - first_byte) / bytes_per_value, prefix_len);
+        }
+        first_byte += count * bytes_per_value;
+        upper += count;
+    }
+    unreachable!("the groups cover every byte value")
+}
+
+/// Reads bits most significant first
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// Position in bits
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn read_bit(&mut self) -> Option<u32> {
+        let byte = *self.data.get(self.position / 8)?;
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+        Some(bit as u32)
+    }
+
+    fn read_bits(&mut self, count: u32) -> Option<u32> {
+        (0..count).try_fold(0, |value, _| Some((value << 1) | self.read_bit()?))
+    }
+}
+
+/// Adaptive Huffman tree over the literal and match length codes
+///
+/// Nodes are kept sorted by frequency, and the children of a node are always
+/// next to each other, so only the first child is stored.
+struct HuffmanTree {
+    /// Frequency of each node, followed by a sentinel larger than any of them
+    frequency: [u32; NODE_COUNT + 1],
+    /// Parent of each node, followed by the leaf node of each code
+    parent: [usize; NODE_COUNT + CODE_COUNT],
+    /// First child of each node, or `NODE_COUNT + code` for a leaf
+    child: [usize; NODE_COUNT],
+}
+
+impl HuffmanTree {
+    /// Builds the starting tree, where every code has a frequency of one
+    fn new() -> Self {
+        let mut tree = Self {
+            frequency: [0; NODE_COUNT + 1],
+            parent: [0; NODE_COUNT + CODE_COUNT],
+            child: [0; NODE_COUNT],
+        };
+        for code in 0..CODE_COUNT {
+            tree.frequency[code] = 1;
+            tree.child[code] = NODE_COUNT + code;
+            tree.parent[NODE_COUNT + code] = code;
+        }
+        for (node, first) in (CODE_COUNT..NODE_COUNT).zip((0..).step_by(2)) {
+            tree.frequency[node] = tree.frequency[first] + tree.frequency[first + 1];
+            tree.child[node] = first;
+            tree.parent[first] = node;
+            tree.parent[first + 1] = node;
+        }
+        tree.frequency[NODE_COUNT] = u32::MAX;
+        tree.parent[ROOT] = 0;
+        tree
+    }
+
+    /// Reads one code, or returns `None` if the input ends first
+    fn decode(&mut self, bits: &mut BitReader) -> Option<usize> {
+        let mut node = self.child[ROOT];
+        while node < NODE_COUNT {
+            node = self.child[node + bits.read_bit()? as usize];
+        }
+        let code = node - NODE_COUNT;
+        self.update(code);
+        Some(code)
+    }
+
+    /// Counts one more use of a code, swapping nodes to keep them sorted
+    fn update(&mut self, code: usize) {
+        if self.frequency[ROOT] == MAX_FREQUENCY {
+            self.rebuild();
+        }
+
+        let mut node = self.parent[NODE_COUNT + code];
+        loop {
+            self.frequency[node] += 1;
+            let frequency = self.frequency[node];
+            if frequency > self.frequency[node + 1] {
+                // Swap with the last node that now has a lower frequency
+                let mut other = node + 1;
+                while frequency > self.frequency[other + 1] {
+                    other += 1;
+                }
+                self.frequency[node] = self.frequency[other];
+                self.frequency[other] = frequency;
+
+                let child = self.child[node];
+                let other_child = self.child[other];
+                self.set_parent(child, other);
+                self.set_parent(other_child, node);
+                self.child[other] = child;
+                self.child[node] = other_child;
+                node = other;
+            }
+
+            // The root's parent is node zero, which is never a parent itself
+            node = self.parent[node];
+            if node == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Points a node's children, or its code for a leaf, back at it
+    fn set_parent(&mut self, child: usize, node: usize) {
+        self.parent[child] = node;
+        if child < NODE_COUNT {
+            self.parent[child + 1] = node;
+        }
+    }
+
+    /// Halves every frequency and rebuilds the tree from the leaves
+    fn rebuild(&mut self) {
+        let mut leaf = 0;
+        for node in 0..NODE_COUNT {
+            if self.child[node] >= NODE_COUNT {
+                self.frequency[leaf] = self.frequency[node].div_ceil(2);
+                self.child[leaf] = self.child[node];
+                leaf += 1;
+            }
+        }
+
+        for (node, first) in (CODE_COUNT..NODE_COUNT).zip((0..).step_by(2)) {
+            let frequency = self.frequency[first] + self.frequency[first + 1];
+            let mut at = node;
+            while frequency < self.frequency[at - 1] {
+                at -= 1;
+            }
+            self.frequency.copy_within(at..node, at + 1);
+            self.frequency[at] = frequency;
+            self.child.copy_within(at..node, at + 1);
+            self.child[at] = first;
+        }
+
+        for node in 0..NODE_COUNT {
+            self.set_parent(self.child[node], node);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// A piece of an LZH stream
+    #[derive(Debug, Clone, Copy)]
+    pub(in crate::map) enum Token {
+        Literal(u8),
+        /// Copies `length` bytes starting `distance` bytes back
+        Match {
+            distance: usize,
+            length: usize,
+        },
+    }
+
+    #[derive(Default)]
+    struct BitWriter {
+        bytes: Vec<u8>,
+        len: usize,
+    }
+
+    impl BitWriter {
+        fn push(&mut self, value: usize, count: u32) {
+            for shift in (0..count).rev() {
+                if self.len.is_multiple_of(8) {
+                    self.bytes.push(0);
+                }
+                let bit = (value >> shift) & 1;
+                *self.bytes.last_mut().unwrap() |= (bit as u8) << (7 - self.len % 8);
+                self.len += 1;
+            }
+        }
+    }
+
+    /// Writes the code for `code` by walking from its leaf up to the root
+    fn encode_code(tree: &mut HuffmanTree, bits: &mut BitWriter, code: usize) {
+        let mut path = Vec::new();
+        let mut node = tree.parent[NODE_COUNT + code];
+        while node != ROOT {
+            let parent = tree.parent[node];
+            path.push(node - tree.child[parent]);
+            node = parent;
+        }
+        for &bit in path.iter().rev() {
+            bits.push(bit, 1);
+        }
+        tree.update(code);
+    }
+
+    /// Compresses a token stream the way the game's encoder would
+    pub(in crate::map) fn encode(tokens: &[Token]) -> Vec<u8> {
+        let mut tree = HuffmanTree::new();
+        let mut bits = BitWriter::default();
+        for &token in tokens {
+            match token {
+                Token::Literal(byte) => encode_code(&mut tree, &mut bits, byte as usize),
+                Token::Match { distance, length } => {
+                    encode_code(&mut tree, &mut bits, length - THRESHOLD - 1 + 256);
+                    let offset = distance - 1;
+                    let first_byte = (0..=255u8)
+                        .find(|&byte| offset_prefix(byte).0 == offset >> 6)
+                        .unwrap();
+                    let prefix_len = offset_prefix(first_byte).1;
+                    bits.push(first_byte as usize >> (8 - prefix_len), prefix_len);
+                    bits.push(offset & 0x3F, 6);
+                }
+            }
+        }
+        bits.bytes
+    }
+
+    fn literals(data: &[u8]) -> Vec<Token> {
+        data.iter().map(|&byte| Token::Literal(byte)).collect()
+    }
+
+    #[test]
+    fn offset_prefixes_cover_all_upper_values() {
+        assert_eq!(offset_prefix(0x00), (0x00, 3));
+        assert_eq!(offset_prefix(0x1F), (0x00, 3));
+        assert_eq!(offset_prefix(0x20), (0x01, 4));
+        assert_eq!(offset_prefix(0x50), (0x04, 5));
+        assert_eq!(offset_prefix(0x90), (0x0C, 6));
+        assert_eq!(offset_prefix(0xC0), (0x18, 7));
+        assert_eq!(offset_prefix(0xFF), (0x3F, 8));
+    }
+
+    #[test]
+    fn decodes_literals_and_matches() {
+        let mut tokens = literals(b"abc");
+        tokens.push(Token::Match {
+            distance: 3,
+            length: 9,
+        });
+        tokens.push(Token::Literal(b'X'));
+        // A match reaching back past the window start reads the initial spaces
+        tokens.push(Token::Match {
+            distance: 4000,
+            length: 3,
+        });
+        tokens.push(Token::Match {
+            distance: 10,
+            length: MAX_MATCH,
+        });
+
+        let mut expected = b"abcabcabcabcX   ".to_vec();
+        for _ in 0..MAX_MATCH {
+            expected.push(expected[expected.len() - 10]);
+        }
+        assert_eq!(decompress(&encode(&tokens)), expected);
+    }
+
+    #[test]
+    fn decodes_long_streams_across_tree_rebuilds() {
+        // Enough codes to push the root frequency past MAX_FREQUENCY twice
+        let data: Vec<u8> = (0..70_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8 % 40)
+            .collect();
+        assert_eq!(decompress(&encode(&literals(&data))), data);
+    }
+
+    #[test]
+    fn stops_at_the_end_of_the_input() {
+        assert!(decompress(&[]).is_empty());
+
+        // A match whose offset is cut off is dropped
+        let mut data = encode(&[
+            Token::Literal(b'a'),
+            Token::Match {
+                distance: 1,
+                length: 5,
+            },
+        ]);
+        // The offset takes nine bits, so dropping the last byte only cuts into it
+        data.pop();
+        assert_eq!(decompress(&data), b"a");
+    }
+}
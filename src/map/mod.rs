@@ -1,8 +1,19 @@
 //! Map-related functionality for OP2MapViewer
 
+pub mod diagnostics;
+pub mod format;
+pub mod json;
 pub mod loader;
+pub mod lzh;
+pub mod summary;
+pub mod tileset;
 pub mod types;
+pub mod vol;
+pub mod writer;
 
 // Re-export commonly used items
-pub use loader::{load_map, load_tilesets, MapLoadError, TilesetCache};
-pub use types::{Cell, CellType, Map, MapInfo, Position, TileInfo};
+pub use diagnostics::{Diagnostics, Severity};
+pub use loader::{load_map, load_tilesets, MapLoadError, TileLayout, Tileset, TilesetCache};
+pub use summary::MapSummary;
+pub use types::{Cell, CellType, Map, MapInfo, Position, TileInfo, TileWord, Unit};
+pub use writer::save_map;
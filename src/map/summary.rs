@@ -0,0 +1,207 @@
+//! Statistics about a loaded map, for reports and scripts
+
+use serde::Serialize;
+
+use super::types::{CellType, Map, MapInfo};
+
+/// An overview of a map's contents
+#[derive(Debug, Clone, Serialize)]
+pub struct MapSummary {
+    pub info: MapInfo,
+    /// Format the map was loaded with, if it came from a file
+    pub format: Option<&'static str>,
+    /// Used tileset slots, in slot order
+    pub tilesets: Vec<TilesetSummary>,
+    pub tile_mapping_count: usize,
+    pub terrain_types: Vec<TerrainTypeSummary>,
+    pub tile_groups: Vec<TileGroupSummary>,
+    /// Number of cells of each cell type present, in cell type order
+    pub cell_types: Vec<CellTypeCount>,
+    pub unit_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TilesetSummary {
+    pub slot: usize,
+    pub name: String,
+    pub num_tiles: u32,
+}
+
+/// The tile mappings belonging to a terrain type
+#[derive(Debug, Clone, Serialize)]
+pub struct TerrainTypeSummary {
+    pub first_mapping: u16,
+    pub last_mapping: u16,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TileGroupSummary {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CellTypeCount {
+    pub cell_type: CellType,
+    pub count: usize,
+}
+
+impl MapSummary {
+    pub fn new(map: &Map) -> Self {
+        let mut histogram = [0usize; CellType::ALL.len()];
+        for cell in map.cells() {
+            histogram[cell.cell_type().index() as usize] += 1;
+        }
+
+        Self {
+            info: map.info.clone(),
+            format: map.info.source_format,
+            tilesets: map
+                .tileset_sources
+                .iter()
+                .enumerate()
+                .filter(|(_, source)| !source.filename.is_empty())
+                .map(|(slot, source)| TilesetSummary {
+                    slot,
+                    name: source.filename.clone(),
+                    num_tiles: source.num_tiles,
+                })
+                .collect(),
+            tile_mapping_count: map.tile_mappings.len(),
+            terrain_types: map
+                .terrain_types
+                .iter()
+                .map(|terrain| TerrainTypeSummary {
+                    first_mapping: terrain.tile_mapping_range.start,
+                    last_mapping: terrain.tile_mapping_range.end,
+                })
+                .collect(),
+            tile_groups: map
+                .tile_groups
+                .iter()
+                .map(|group| TileGroupSummary {
+                    name: group.display_name(),
+                    width: group.width,
+                    height: group.height,
+                })
+                .collect(),
+            cell_types: CellType::ALL
+                .iter()
+                .zip(histogram)
+                .filter(|(_, count)| *count > 0)
+                .map(|(&cell_type, count)| CellTypeCount { cell_type, count })
+                .collect(),
+            unit_count: map.units.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::types::{
+        MappingRange, TerrainType, TileGroup, TileMapping, TileWord, TilesetSource,
+    };
+    use serde_json::json;
+
+    fn small_map() -> Map {
+        let mut map = Map::new(MapInfo {
+            width: 4,
+            height: 2,
+            name: "test".into(),
+            ..Default::default()
+        });
+        let cell_types = [
+            CellType::Impassible1,
+            CellType::FastPassible1,
+            CellType::Tube2,
+            CellType::Impassible1,
+            CellType::FastPassible1,
+            CellType::Impassible1,
+            CellType::LavaWall,
+            CellType::FastPassible1,
+        ];
+        for (i, cell_type) in cell_types.into_iter().enumerate() {
+            *map.tile_mut(i as i32 % 4, i as i32 / 4).unwrap() =
+                TileWord::new(cell_type.index(), 0);
+        }
+
+        map.tileset_sources = vec![
+            TilesetSource {
+                filename: "well0001".into(),
+                num_tiles: 100,
+            },
+            TilesetSource {
+                filename: String::new(),
+                num_tiles: 0,
+            },
+            TilesetSource {
+                filename: "well0004".into(),
+                num_tiles: 50,
+            },
+        ];
+        map.tile_mappings = vec![TileMapping::default(); 3];
+        map.terrain_types = vec![TerrainType {
+            tile_mapping_range: MappingRange { start: 0, end: 2 },
+            ..Default::default()
+        }];
+        map.tile_groups = vec![TileGroup {
+            width: 2,
+            height: 1,
+            mapping_indices: vec![0, 1],
+            name: b"Ridge\0\0".to_vec(),
+        }];
+        map
+    }
+
+    #[test]
+    fn counts_cell_types_in_table_order() {
+        let summary = MapSummary::new(&small_map());
+        let counts: Vec<_> = summary
+            .cell_types
+            .iter()
+            .map(|entry| (entry.cell_type, entry.count))
+            .collect();
+        assert_eq!(
+            counts,
+            [
+                (CellType::FastPassible1, 3),
+                (CellType::Impassible1, 3),
+                (CellType::LavaWall, 1),
+                (CellType::Tube2, 1),
+            ]
+        );
+        assert_eq!(counts.iter().map(|(_, count)| count).sum::<usize>(), 8);
+    }
+
+    #[test]
+    fn serialises_the_info_json_fields() {
+        let value = serde_json::to_value(MapSummary::new(&small_map())).unwrap();
+
+        assert_eq!(value["info"]["name"], "test");
+        assert_eq!(value["info"]["width"], 4);
+        assert_eq!(value["format"], json!(null));
+        assert_eq!(
+            value["tilesets"],
+            json!([
+                { "slot": 0, "name": "well0001", "num_tiles": 100 },
+                { "slot": 2, "name": "well0004", "num_tiles": 50 },
+            ])
+        );
+        assert_eq!(value["tile_mapping_count"], 3);
+        assert_eq!(
+            value["terrain_types"],
+            json!([{ "first_mapping": 0, "last_mapping": 2 }])
+        );
+        assert_eq!(
+            value["tile_groups"],
+            json!([{ "name": "Ridge", "width": 2, "height": 1 }])
+        );
+        assert_eq!(
+            value["cell_types"][0],
+            json!({ "cell_type": "FastPassible1", "count": 3 })
+        );
+        assert_eq!(value["unit_count"], 0);
+    }
+}
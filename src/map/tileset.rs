@@ -0,0 +1,269 @@
+//! Decoding of Outpost 2 tileset images
+//!
+//! The game ships its tilesets in a custom container rather than as standard
+//! bitmaps. The container is a sequence of tagged sections:
+//!
+//! ```text
+//! "PBMP" <len>
+//!   "head" <len>  tag count, pixel width, pixel height, bit depth, flags
+//!   "PPAL" <len>
+//!     "head" <len>  tag count
+//!     "data" <len>  256 palette entries (red, green, blue, unused)
+//!   "data" <len>  8-bit palette indices, top row first
+//! ```
+
+use image::{DynamicImage, RgbaImage};
+
+use super::loader::MapLoadError;
+
+/// Section tag at the start of a custom tileset file
+const PBMP_TAG: &[u8; 4] = b"PBMP";
+const HEAD_TAG: &[u8; 4] = b"head";
+const PPAL_TAG: &[u8; 4] = b"PPAL";
+const DATA_TAG: &[u8; 4] = b"data";
+
+const PALETTE_ENTRIES: usize = 256;
+
+/// Returns true if the data starts with the custom tileset signature
+pub fn is_pbmp(data: &[u8]) -> bool {
+    data.starts_with(PBMP_TAG)
+}
+
+/// Loads a tileset image in either the custom PBMP format or any format the
+/// `image` crate understands
+pub fn load_tileset_image(data: &[u8]) -> Result<DynamicImage, MapLoadError> {
+    if is_pbmp(data) {
+        return decode_pbmp(data);
+    }
+
+    // BMP files from OP2 might need special handling
+    match image::load_from_memory(data) {
+        Ok(img) => Ok(img),
+        Err(e) => image::load_from_memory_with_format(data, image::ImageFormat::Bmp)
+            .map_err(|_| MapLoadError::ImageError(e)),
+    }
+}
+
+/// Decodes a custom PBMP tileset into an RGBA image
+pub fn decode_pbmp(data: &[u8]) -> Result<DynamicImage, MapLoadError> {
+    let mut reader = SectionReader { data, offset: 0 };
+
+    reader.expect_section(PBMP_TAG)?;
+
+    let head_len = reader.expect_section(HEAD_TAG)?;
+    if head_len < 20 {
+        return Err(invalid(format!(
+            "PBMP head section too short: {} bytes",
+            head_len
+        )));
+    }
+    let head = reader.take(head_len as usize)?;
+    let field = |index: usize| {
+        let start = index * 4;
+        u32::from_le_bytes([
+            head[start],
+            head[start + 1],
+            head[start + 2],
+            head[start + 3],
+        ])
+    };
+    let width = field(1);
+    let height = field(2);
+    let bit_depth = field(3);
+
+    if bit_depth != 8 {
+        return Err(invalid(format!(
+            "Unsupported PBMP bit depth: {}",
+            bit_depth
+        )));
+    }
+    if width == 0 || height == 0 {
+        return Err(invalid(format!("Invalid PBMP size: {}x{}", width, height)));
+    }
+
+    reader.expect_section(PPAL_TAG)?;
+    let palette_head_len = reader.expect_section(HEAD_TAG)?;
+    reader.take(palette_head_len as usize)?;
+    let palette_len = reader.expect_section(DATA_TAG)? as usize;
+    if palette_len < PALETTE_ENTRIES * 4 {
+        return Err(invalid(format!(
+            "PBMP palette too short: {} bytes",
+            palette_len
+        )));
+    }
+    let palette = reader.take(palette_len)?;
+
+    let pixels_len = reader.expect_section(DATA_TAG)? as usize;
+    // Rows are padded to a multiple of four bytes
+    let pitch = (width as usize + 3) & !3;
+    if pixels_len < pitch * height as usize {
+        return Err(invalid(format!(
+            "PBMP pixel data too short: {} bytes for {}x{}",
+            pixels_len, width, height
+        )));
+    }
+    let pixels = reader.take(pixels_len)?;
+
+    let mut image = RgbaImage::new(width, height);
+    for (y, row) in pixels.chunks_exact(pitch).take(height as usize).enumerate() {
+        for (x, &index) in row.iter().take(width as usize).enumerate() {
+            let entry = &palette[index as usize * 4..index as usize * 4 + 3];
+            image.put_pixel(
+                x as u32,
+                y as u32,
+                image::Rgba([entry[0], entry[1], entry[2], 255]),
+            );
+        }
+    }
+
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+fn invalid(message: String) -> MapLoadError {
+    MapLoadError::InvalidFormat(message)
+}
+
+/// Walks the tagged sections of a PBMP file
+struct SectionReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> SectionReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], MapLoadError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| {
+                invalid(format!(
+                    "PBMP data truncated at offset {:#x} (wanted {} bytes)",
+                    self.offset, len
+                ))
+            })?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    /// Reads a section header, checks its tag and returns the section length
+    fn expect_section(&mut self, tag: &[u8; 4]) -> Result<u32, MapLoadError> {
+        let offset = self.offset;
+        let header = self.take(8)?;
+        if &header[0..4] != tag {
+            return Err(invalid(format!(
+                "Expected PBMP section {:?} at offset {:#x}, found {:?}",
+                String::from_utf8_lossy(tag),
+                offset,
+                String::from_utf8_lossy(&header[0..4])
+            )));
+        }
+        Ok(u32::from_le_bytes([
+            header[4], header[5], header[6], header[7],
+        ]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(tag: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = tag.to_vec();
+        data.extend((body.len() as u32).to_le_bytes());
+        data.extend(body);
+        data
+    }
+
+    fn head(width: u32, height: u32, bit_depth: u32) -> Vec<u8> {
+        [5, width, height, bit_depth, 8]
+            .into_iter()
+            .flat_map(u32::to_le_bytes)
+            .collect()
+    }
+
+    /// Palette where entry `i` is (i, i + 1, i + 2) with an unused fourth byte
+    fn palette() -> Vec<u8> {
+        (0..=255u8)
+            .flat_map(|i| [i, i.wrapping_add(1), i.wrapping_add(2), 0xEE])
+            .collect()
+    }
+
+    fn pbmp(head: &[u8], palette: &[u8], pixels: &[u8]) -> Vec<u8> {
+        let mut body = section(HEAD_TAG, head);
+        let mut ppal = section(HEAD_TAG, &1u32.to_le_bytes());
+        ppal.extend(section(DATA_TAG, palette));
+        body.extend(section(PPAL_TAG, &ppal));
+        body.extend(section(DATA_TAG, pixels));
+        section(PBMP_TAG, &body)
+    }
+
+    fn decode_error(data: &[u8]) -> String {
+        match decode_pbmp(data) {
+            Err(MapLoadError::InvalidFormat(message)) => message,
+            other => panic!("expected InvalidFormat, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn decodes_pixels_through_the_palette() {
+        // 3x2 image; rows are padded to four bytes
+        let pixels = [1, 2, 3, 0xAA, 10, 20, 30, 0xAA];
+        let data = pbmp(&head(3, 2, 8), &palette(), &pixels);
+        assert!(is_pbmp(&data));
+
+        let image = load_tileset_image(&data).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (3, 2));
+        for (x, y, index) in [(0, 0, 1), (1, 0, 2), (2, 0, 3), (0, 1, 10), (2, 1, 30)] {
+            assert_eq!(
+                image.get_pixel(x, y).0,
+                [index, index + 1, index + 2, 255],
+                "pixel ({}, {})",
+                x,
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn palette_entries_are_read_red_green_blue() {
+        let mut palette = vec![0; PALETTE_ENTRIES * 4];
+        palette[4..8].copy_from_slice(&[0xFF, 0x80, 0x00, 0x00]);
+        let data = pbmp(&head(1, 1, 8), &palette, &[1, 0, 0, 0]);
+
+        let image = decode_pbmp(&data).unwrap().to_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [0xFF, 0x80, 0x00, 255]);
+    }
+
+    #[test]
+    fn rejects_truncated_pixel_data() {
+        // The section claims enough bytes for the image but the file ends early
+        let mut data = pbmp(&head(4, 2, 8), &palette(), &[0; 8]);
+        data.truncate(data.len() - 3);
+        assert!(decode_error(&data).contains("truncated"));
+
+        // The section itself is too short for the image
+        let data = pbmp(&head(4, 2, 8), &palette(), &[0; 4]);
+        assert!(decode_error(&data).contains("pixel data too short"));
+    }
+
+    #[test]
+    fn rejects_short_head_section() {
+        let data = pbmp(&head(4, 2, 8)[..16], &palette(), &[0; 8]);
+        assert!(decode_error(&data).contains("head section too short"));
+    }
+
+    #[test]
+    fn rejects_bit_depths_other_than_eight() {
+        for bit_depth in [1, 4, 16, 24, 32] {
+            let data = pbmp(&head(4, 2, bit_depth), &palette(), &[0; 8]);
+            assert!(decode_error(&data).contains("bit depth"));
+        }
+    }
+
+    #[test]
+    fn rejects_short_palette() {
+        let data = pbmp(&head(4, 2, 8), &palette()[..1020], &[0; 8]);
+        assert!(decode_error(&data).contains("palette too short"));
+    }
+}
@@ -3,8 +3,12 @@
 use std::fmt;
 use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
+
+use super::json::MapDocument;
+
 /// A 2D position in the map
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
@@ -16,84 +20,437 @@ impl Position {
     }
 }
 
-/// Information about a tile from a tileset
-#[derive(Debug, Clone)]
-pub struct TileInfo {
-    pub tileset_name: String,
+/// Information about a tile from a tileset, resolved through the map's tile mappings
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TileInfo<'a> {
+    /// Slot in the map's tileset table; the name is stored once in that table
+    pub tileset_index: u16,
+    pub tileset_name: &'a str,
     pub tile_index: u32,
+    pub mapping: &'a TileMapping,
 }
 
-/// Map cell types in Outpost 2
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Map cell types in Outpost 2, in the order of the 5-bit cell type field
+///
+/// The cell type controls how units move over a tile; the tile graphic is
+/// chosen separately through the tile mapping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[repr(u8)]
 pub enum CellType {
-    Normal,
-    Lava(u8),    // Variant indicates lava color/type
-    Microbe(u8), // Variant indicates microbe growth stage
-    Mine(bool),  // Boolean indicates if mine is depleted
-    Dirt(u8),    // Variant indicates dirt type
-    Rock(u8),    // Variant indicates rock type
-    Tube(u8),    // Variant indicates tube connections
-    Wall(u8),    // Variant indicates wall type
+    #[default]
+    FastPassible1 = 0,
+    Impassible2,
+    SlowPassible1,
+    SlowPassible2,
+    MediumPassible1,
+    MediumPassible2,
+    Impassible1,
+    FastPassible2,
+    NorthCliffs,
+    CliffsHighSide,
+    CliffsLowSide,
+    VentsAndFumaroles,
+    ZPad12,
+    ZPad13,
+    ZPad14,
+    ZPad15,
+    ZPad16,
+    ZPad17,
+    ZPad18,
+    ZPad19,
+    ZPad20,
+    DozedArea,
+    Rubble,
+    NormalWall,
+    MicrobeWall,
+    LavaWall,
+    Tube0,
+    Tube1,
+    Tube2,
+    Tube3,
+    Tube4,
+    Tube5,
 }
 
-impl fmt::Display for CellType {
+/// Movement speed class of a cell type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CellSpeed {
+    Fast,
+    Medium,
+    Slow,
+    Impassible,
+}
+
+impl fmt::Display for CellSpeed {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            CellType::Normal => write!(f, "Normal Ground"),
-            CellType::Lava(variant) => write!(f, "Lava Type {}", variant),
-            CellType::Microbe(stage) => write!(f, "Microbe Growth Stage {}", stage),
-            CellType::Mine(depleted) => write!(
-                f,
-                "Mine ({})",
-                if *depleted { "Depleted" } else { "Active" }
-            ),
-            CellType::Dirt(variant) => write!(f, "Dirt Type {}", variant),
-            CellType::Rock(variant) => write!(f, "Rock Type {}", variant),
-            CellType::Tube(connections) => write!(f, "Tube (Connections: {:08b})", connections),
-            CellType::Wall(variant) => write!(f, "Wall Type {}", variant),
+            CellSpeed::Fast => write!(f, "Fast"),
+            CellSpeed::Medium => write!(f, "Medium"),
+            CellSpeed::Slow => write!(f, "Slow"),
+            CellSpeed::Impassible => write!(f, "Impassible"),
+        }
+    }
+}
+
+impl CellType {
+    /// Every cell type, indexed by its value in the tile word
+    pub const ALL: [CellType; 32] = [
+        CellType::FastPassible1,
+        CellType::Impassible2,
+        CellType::SlowPassible1,
+        CellType::SlowPassible2,
+        CellType::MediumPassible1,
+        CellType::MediumPassible2,
+        CellType::Impassible1,
+        CellType::FastPassible2,
+        CellType::NorthCliffs,
+        CellType::CliffsHighSide,
+        CellType::CliffsLowSide,
+        CellType::VentsAndFumaroles,
+        CellType::ZPad12,
+        CellType::ZPad13,
+        CellType::ZPad14,
+        CellType::ZPad15,
+        CellType::ZPad16,
+        CellType::ZPad17,
+        CellType::ZPad18,
+        CellType::ZPad19,
+        CellType::ZPad20,
+        CellType::DozedArea,
+        CellType::Rubble,
+        CellType::NormalWall,
+        CellType::MicrobeWall,
+        CellType::LavaWall,
+        CellType::Tube0,
+        CellType::Tube1,
+        CellType::Tube2,
+        CellType::Tube3,
+        CellType::Tube4,
+        CellType::Tube5,
+    ];
+
+    /// Converts the 5-bit cell type field; returns `None` for values above 31
+    pub fn from_index(index: u8) -> Option<Self> {
+        Self::ALL.get(index as usize).copied()
+    }
+
+    /// The value stored in the tile word
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+
+    /// The name used by the game and its SDK
+    pub fn name(self) -> &'static str {
+        match self {
+            CellType::FastPassible1 => "FastPassible1",
+            CellType::Impassible2 => "Impassible2",
+            CellType::SlowPassible1 => "SlowPassible1",
+            CellType::SlowPassible2 => "SlowPassible2",
+            CellType::MediumPassible1 => "MediumPassible1",
+            CellType::MediumPassible2 => "MediumPassible2",
+            CellType::Impassible1 => "Impassible1",
+            CellType::FastPassible2 => "FastPassible2",
+            CellType::NorthCliffs => "NorthCliffs",
+            CellType::CliffsHighSide => "CliffsHighSide",
+            CellType::CliffsLowSide => "CliffsLowSide",
+            CellType::VentsAndFumaroles => "VentsAndFumaroles",
+            CellType::ZPad12 => "zPad12",
+            CellType::ZPad13 => "zPad13",
+            CellType::ZPad14 => "zPad14",
+            CellType::ZPad15 => "zPad15",
+            CellType::ZPad16 => "zPad16",
+            CellType::ZPad17 => "zPad17",
+            CellType::ZPad18 => "zPad18",
+            CellType::ZPad19 => "zPad19",
+            CellType::ZPad20 => "zPad20",
+            CellType::DozedArea => "DozedArea",
+            CellType::Rubble => "Rubble",
+            CellType::NormalWall => "NormalWall",
+            CellType::MicrobeWall => "MicrobeWall",
+            CellType::LavaWall => "LavaWall",
+            CellType::Tube0 => "Tube0",
+            CellType::Tube1 => "Tube1",
+            CellType::Tube2 => "Tube2",
+            CellType::Tube3 => "Tube3",
+            CellType::Tube4 => "Tube4",
+            CellType::Tube5 => "Tube5",
+        }
+    }
+
+    /// Short description of the terrain this cell type is normally used for
+    pub fn description(self) -> &'static str {
+        match self {
+            CellType::FastPassible1 => "Rock vegetation",
+            CellType::Impassible2 => "Meteor craters, cracks and crevasses",
+            CellType::SlowPassible1 => "Dark lava rock",
+            CellType::SlowPassible2 => "Rippled dirt and lava rock bumps",
+            CellType::MediumPassible1 => "Dirt",
+            CellType::MediumPassible2 => "Lava rock",
+            CellType::Impassible1 => "Mounds and ice caps",
+            CellType::FastPassible2 => "Rock",
+            CellType::NorthCliffs => "North facing cliffs",
+            CellType::CliffsHighSide => "High side of cliffs",
+            CellType::CliffsLowSide => "Low side of cliffs",
+            CellType::VentsAndFumaroles => "Vents and fumaroles",
+            CellType::ZPad12
+            | CellType::ZPad13
+            | CellType::ZPad14
+            | CellType::ZPad15
+            | CellType::ZPad16
+            | CellType::ZPad17
+            | CellType::ZPad18
+            | CellType::ZPad19
+            | CellType::ZPad20 => "Unused",
+            CellType::DozedArea => "Bulldozed ground",
+            CellType::Rubble => "Rubble",
+            CellType::NormalWall => "Wall",
+            CellType::MicrobeWall => "Microbe wall",
+            CellType::LavaWall => "Lava wall",
+            CellType::Tube0
+            | CellType::Tube1
+            | CellType::Tube2
+            | CellType::Tube3
+            | CellType::Tube4
+            | CellType::Tube5 => "Tube",
+        }
+    }
+
+    /// How fast units move over this cell type
+    pub fn speed(self) -> CellSpeed {
+        match self {
+            CellType::FastPassible1
+            | CellType::FastPassible2
+            | CellType::DozedArea
+            | CellType::Tube0
+            | CellType::Tube1
+            | CellType::Tube2
+            | CellType::Tube3
+            | CellType::Tube4
+            | CellType::Tube5 => CellSpeed::Fast,
+            CellType::MediumPassible1 | CellType::MediumPassible2 | CellType::Rubble => {
+                CellSpeed::Medium
+            }
+            CellType::SlowPassible1 | CellType::SlowPassible2 => CellSpeed::Slow,
+            _ => CellSpeed::Impassible,
+        }
+    }
+
+    pub fn is_passable(self) -> bool {
+        self.speed() != CellSpeed::Impassible
+    }
+
+    pub fn is_wall(self) -> bool {
+        matches!(
+            self,
+            CellType::NormalWall | CellType::MicrobeWall | CellType::LavaWall
+        )
+    }
+
+    pub fn is_tube(self) -> bool {
+        matches!(
+            self,
+            CellType::Tube0
+                | CellType::Tube1
+                | CellType::Tube2
+                | CellType::Tube3
+                | CellType::Tube4
+                | CellType::Tube5
+        )
+    }
+}
+
+impl fmt::Display for CellType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name(), self.description())
+    }
+}
+
+/// A packed 32-bit tile word as stored in the map file
+///
+/// Bit layout, from least significant:
+/// - 0..5: cell type
+/// - 5..16: tile mapping index
+/// - 16..27: unit index
+/// - 27: lava, 28: lava possible, 29: expand, 30: microbe, 31: wall or building
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TileWord(pub u32);
+
+impl TileWord {
+    const CELL_TYPE_SHIFT: u32 = 0;
+    const CELL_TYPE_MASK: u32 = 0x1F;
+    const MAPPING_SHIFT: u32 = 5;
+    const MAPPING_MASK: u32 = 0x7FF;
+    const UNIT_SHIFT: u32 = 16;
+    const UNIT_MASK: u32 = 0x7FF;
+    const LAVA_BIT: u32 = 27;
+    const LAVA_POSSIBLE_BIT: u32 = 28;
+    const EXPAND_BIT: u32 = 29;
+    const MICROBE_BIT: u32 = 30;
+    const WALL_OR_BUILDING_BIT: u32 = 31;
+
+    /// Creates a word with the given cell type and tile mapping and all other fields clear
+    pub fn new(cell_type: u8, tile_mapping_index: u16) -> Self {
+        let mut word = Self(0);
+        word.set_cell_type_index(cell_type);
+        word.set_tile_mapping_index(tile_mapping_index);
+        word
+    }
+
+    pub fn raw(self) -> u32 {
+        self.0
+    }
+
+    fn field(self, shift: u32, mask: u32) -> u32 {
+        (self.0 >> shift) & mask
+    }
+
+    fn set_field(&mut self, shift: u32, mask: u32, value: u32) {
+        debug_assert!(
+            value <= mask,
+            "value {} does not fit mask {:#x}",
+            value,
+            mask
+        );
+        self.0 = (self.0 & !(mask << shift)) | ((value & mask) << shift);
+    }
+
+    fn flag(self, bit: u32) -> bool {
+        self.0 & (1 << bit) != 0
+    }
+
+    fn set_flag(&mut self, bit: u32, value: bool) {
+        if value {
+            self.0 |= 1 << bit;
+        } else {
+            self.0 &= !(1 << bit);
         }
     }
+
+    /// The raw 5-bit cell type
+    pub fn cell_type_index(self) -> u8 {
+        self.field(Self::CELL_TYPE_SHIFT, Self::CELL_TYPE_MASK) as u8
+    }
+
+    pub fn cell_type(self) -> CellType {
+        // The field is 5 bits wide, so every value has a cell type
+        CellType::ALL[self.cell_type_index() as usize]
+    }
+
+    pub fn set_cell_type(&mut self, cell_type: CellType) {
+        self.set_cell_type_index(cell_type.index());
+    }
+
+    pub fn set_cell_type_index(&mut self, value: u8) {
+        self.set_field(Self::CELL_TYPE_SHIFT, Self::CELL_TYPE_MASK, value as u32);
+    }
+
+    /// Index into the map's tile mapping table
+    pub fn tile_mapping_index(self) -> u16 {
+        self.field(Self::MAPPING_SHIFT, Self::MAPPING_MASK) as u16
+    }
+
+    pub fn set_tile_mapping_index(&mut self, value: u16) {
+        self.set_field(Self::MAPPING_SHIFT, Self::MAPPING_MASK, value as u32);
+    }
+
+    /// Index of the unit occupying the tile, 0 when empty
+    pub fn unit_index(self) -> u16 {
+        self.field(Self::UNIT_SHIFT, Self::UNIT_MASK) as u16
+    }
+
+    pub fn set_unit_index(&mut self, value: u16) {
+        self.set_field(Self::UNIT_SHIFT, Self::UNIT_MASK, value as u32);
+    }
+
+    /// Lava currently covers the tile
+    pub fn is_lava(self) -> bool {
+        self.flag(Self::LAVA_BIT)
+    }
+
+    pub fn set_lava(&mut self, value: bool) {
+        self.set_flag(Self::LAVA_BIT, value);
+    }
+
+    /// Lava is allowed to flow onto the tile
+    pub fn is_lava_possible(self) -> bool {
+        self.flag(Self::LAVA_POSSIBLE_BIT)
+    }
+
+    pub fn set_lava_possible(&mut self, value: bool) {
+        self.set_flag(Self::LAVA_POSSIBLE_BIT, value);
+    }
+
+    /// Lava or microbe spreading from this tile is still expanding
+    pub fn is_expand(self) -> bool {
+        self.flag(Self::EXPAND_BIT)
+    }
+
+    pub fn set_expand(&mut self, value: bool) {
+        self.set_flag(Self::EXPAND_BIT, value);
+    }
+
+    /// The Blight (microbe) covers the tile
+    pub fn is_microbe(self) -> bool {
+        self.flag(Self::MICROBE_BIT)
+    }
+
+    pub fn set_microbe(&mut self, value: bool) {
+        self.set_flag(Self::MICROBE_BIT, value);
+    }
+
+    /// A wall or building occupies the tile
+    pub fn is_wall_or_building(self) -> bool {
+        self.flag(Self::WALL_OR_BUILDING_BIT)
+    }
+
+    pub fn set_wall_or_building(&mut self, value: bool) {
+        self.set_flag(Self::WALL_OR_BUILDING_BIT, value);
+    }
+}
+
+impl From<u32> for TileWord {
+    fn from(raw: u32) -> Self {
+        Self(raw)
+    }
+}
+
+impl From<TileWord> for u32 {
+    fn from(word: TileWord) -> Self {
+        word.0
+    }
 }
 
-/// A single cell in the map
-#[derive(Debug, Clone)]
+/// A view of a single cell in the map
+///
+/// Cells aren't stored individually: [`Map::get_cell`] assembles this from the
+/// packed tile word and the few per-cell extras, so it is cheap to create and copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Cell {
     pub position: Position,
-    pub cell_type: CellType,
+    /// The tile word this cell was decoded from
+    pub tile: TileWord,
     pub height: u8,
     pub has_wreckage: bool,
     pub has_unit: bool,
-    pub tile_info: Option<TileInfo>,
+    /// The cell as stored in a FORM2 map, for maps loaded from one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub form2: Option<Form2Cell>,
 }
 
 impl Cell {
-    pub fn new(position: Position, cell_type: CellType, height: u8) -> Self {
-        Self {
-            position,
-            cell_type,
-            height,
-            has_wreckage: false,
-            has_unit: false,
-            tile_info: None,
-        }
+    pub fn cell_type(&self) -> CellType {
+        self.tile.cell_type()
     }
 
     pub fn description(&self) -> String {
-        let tile_info = match &self.tile_info {
-            Some(info) => format!(
-                "Tileset: {} (Index: {})\n",
-                info.tileset_name, info.tile_index
-            ),
-            None => String::new(),
-        };
-
         format!(
-            "Position: ({}, {})\nType: {}\nHeight: {}\n{}{}{}",
+            "Position: ({}, {})\nType: {}\nHeight: {}\nTile mapping: {}\n{}{}",
             self.position.x,
             self.position.y,
-            self.cell_type,
+            self.cell_type(),
             self.height,
-            tile_info,
+            self.tile.tile_mapping_index(),
             if self.has_wreckage {
                 "Contains wreckage\n"
             } else {
@@ -104,8 +461,98 @@ impl Cell {
     }
 }
 
+/// Cell kinds of the FORM2 map format, in the order of their codes
+///
+/// FORM2 predates the game's cell type table and describes cells in its own
+/// terms; [`Form2Kind::to_tile_word`] translates them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Form2Kind {
+    Normal,
+    Dirt,
+    /// Variant is the lava color
+    Lava,
+    /// Variant is the growth stage
+    Microbe,
+    /// Variant is non-zero when the mine is depleted
+    Mine,
+    Rock,
+    /// Variant holds the tube connection bits
+    Tube,
+    /// Variant 0 is a normal wall, 1 a lava wall and 2 a microbe wall
+    Wall,
+}
+
+impl Form2Kind {
+    pub const ALL: [Form2Kind; 8] = [
+        Form2Kind::Normal,
+        Form2Kind::Dirt,
+        Form2Kind::Lava,
+        Form2Kind::Microbe,
+        Form2Kind::Mine,
+        Form2Kind::Rock,
+        Form2Kind::Tube,
+        Form2Kind::Wall,
+    ];
+
+    /// Looks up the kind stored in a FORM2 cell's first byte
+    pub fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.get(code as usize).copied()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Form2Kind::Normal => "Normal",
+            Form2Kind::Dirt => "Dirt",
+            Form2Kind::Lava => "Lava",
+            Form2Kind::Microbe => "Microbe",
+            Form2Kind::Mine => "Mine",
+            Form2Kind::Rock => "Rock",
+            Form2Kind::Tube => "Tube",
+            Form2Kind::Wall => "Wall",
+        }
+    }
+
+    /// The closest game tile word: a cell type plus the lava, microbe and wall flags
+    ///
+    /// Mines are units in the game rather than terrain, so they become plain
+    /// ground; the FORM2 kind and variant stay available on [`Cell::form2`].
+    pub fn to_tile_word(self, variant: u8) -> TileWord {
+        let cell_type = match self {
+            Form2Kind::Normal | Form2Kind::Lava | Form2Kind::Microbe | Form2Kind::Mine => {
+                CellType::FastPassible1
+            }
+            Form2Kind::Dirt => CellType::DozedArea,
+            Form2Kind::Rock => CellType::Impassible1,
+            Form2Kind::Tube => CellType::Tube0,
+            Form2Kind::Wall => match variant {
+                1 => CellType::LavaWall,
+                2 => CellType::MicrobeWall,
+                _ => CellType::NormalWall,
+            },
+        };
+        let mut word = TileWord::new(cell_type.index(), 0);
+        match self {
+            Form2Kind::Lava => {
+                word.set_lava(true);
+                word.set_lava_possible(true);
+            }
+            Form2Kind::Microbe => word.set_microbe(true),
+            Form2Kind::Wall => word.set_wall_or_building(true),
+            _ => {}
+        }
+        word
+    }
+}
+
+/// A cell as stored in a FORM2 map
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Form2Cell {
+    pub kind: Form2Kind,
+    pub variant: u8,
+}
+
 /// Map metadata and dimensions
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct MapInfo {
     pub width: u32,
     pub height: u32,
@@ -113,62 +560,581 @@ pub struct MapInfo {
     pub description: String,
     pub author: String,
     pub requirements: Vec<String>,
+    /// Version tag from the file header (0x1011 for all retail maps)
+    pub version_tag: u32,
+    /// Whether the header marks this as a saved game rather than a map
+    pub is_saved_game: bool,
+    /// Name of the [`MapFormat`](super::format::MapFormat) the map was loaded with
+    #[serde(skip)]
+    pub source_format: Option<&'static str>,
 }
 
-impl Default for MapInfo {
-    fn default() -> Self {
-        Self {
-            width: 0,
-            height: 0,
-            name: String::new(),
-            description: String::new(),
-            author: String::new(),
-            requirements: Vec::new(),
-        }
+/// Visible map area in tile coordinates, as stored in the map file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClipRect {
+    pub x1: i32,
+    pub y1: i32,
+    pub x2: i32,
+    pub y2: i32,
+}
+
+/// An entry in the map's tileset table
+///
+/// Maps reserve a fixed number of slots; unused slots have an empty filename.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TilesetSource {
+    pub filename: String,
+    pub num_tiles: u32,
+}
+
+/// Maps a tile's mapping index onto a graphic inside a tileset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TileMapping {
+    pub tileset_index: u16,
+    pub tile_graphic_index: u16,
+    /// Number of extra graphics following `tile_graphic_index` used for animation
+    pub animation_count: u16,
+    /// Game ticks each animation frame is shown for
+    pub animation_delay: u16,
+}
+
+impl TileMapping {
+    pub fn is_animated(&self) -> bool {
+        self.animation_count > 0
+    }
+
+    /// Number of graphics the mapping cycles through, including the first
+    pub fn frame_count(&self) -> u32 {
+        self.animation_count as u32 + 1
+    }
+
+    /// Ticks each frame is shown for; a delay of 0 is treated as 1
+    pub fn frame_ticks(&self) -> u64 {
+        (self.animation_delay as u64).max(1)
+    }
+
+    /// Tileset graphic shown after `tick` game ticks of animation
+    pub fn graphic_at(&self, tick: u64) -> u32 {
+        let frame = (tick / self.frame_ticks()) % self.frame_count() as u64;
+        self.tile_graphic_index as u32 + frame as u32
+    }
+
+    /// Every tileset graphic the mapping shows, in animation order
+    pub fn graphics(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.frame_count() as u64).map(|frame| self.graphic_at(frame * self.frame_ticks()))
+    }
+}
+
+/// An inclusive range of tile mapping indices
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MappingRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+/// Describes which tile mappings belong to a terrain (e.g. rock, sand, lava rock)
+/// and which mappings the game uses when it modifies that terrain.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TerrainType {
+    pub tile_mapping_range: MappingRange,
+    pub bulldozed_mapping: u16,
+    pub rubble_mapping: u16,
+    pub tube_mappings: [u16; 6],
+    /// Wall mappings grouped by wall type, 16 connection variants each
+    pub wall_mappings: [[u16; 16]; 5],
+    pub lava_mapping: u16,
+    pub flat: [u16; 3],
+    pub tube_connection_mappings: [u16; 16],
+    pub scorched_mapping: u16,
+    pub scorched_ranges: [MappingRange; 3],
+    pub unknown: [u16; 15],
+}
+
+/// A named block of tile mappings, such as a cliff section or crater
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TileGroup {
+    pub width: u32,
+    pub height: u32,
+    /// Row-major tile mapping indices, `width * height` entries
+    pub mapping_indices: Vec<u32>,
+    /// Raw name bytes; some editors leave uninitialised memory here
+    #[serde(with = "super::json::text_or_hex")]
+    pub name: Vec<u8>,
+}
+
+impl TileGroup {
+    /// The group name as text, without trailing padding
+    ///
+    /// Control characters from uninitialised name bytes are replaced so the
+    /// name is safe to print.
+    pub fn display_name(&self) -> String {
+        String::from_utf8_lossy(&self.name)
+            .trim_end_matches('\0')
+            .chars()
+            .map(|c| {
+                if c.is_control() {
+                    char::REPLACEMENT_CHARACTER
+                } else {
+                    c
+                }
+            })
+            .collect()
+    }
+}
+
+/// Number of players in a game: six human or AI players and Gaia
+pub const MAX_PLAYERS: u8 = 7;
+
+/// Player number Gaia owns neutral units under
+pub const GAIA_PLAYER: u8 = 6;
+
+/// Size of a unit record in the released game's saved games
+pub const UNIT_RECORD_SIZE: usize = 120;
+
+/// Offset of the unit type in a unit record
+///
+/// In memory the record starts with the unit's class pointer; saved games
+/// store the unit type there instead so the class can be restored on load.
+const UNIT_RECORD_TYPE_OFFSET: usize = 0x00;
+
+/// Offset of the byte holding the owner (low nibble) and creator (high nibble)
+const UNIT_RECORD_OWNER_OFFSET: usize = 0x41;
+
+/// Names of the vehicle and structure unit types, indexed by type
+///
+/// Types past the end of the table are weapons, disasters and other objects
+/// that never occupy a tile.
+const UNIT_TYPE_NAMES: [&str; 0x3B] = [
+    "None",
+    "Cargo Truck",
+    "ConVec",
+    "Spider",
+    "Scorpion",
+    "Lynx",
+    "Panther",
+    "Tiger",
+    "Robo-Surveyor",
+    "Robo-Miner",
+    "GeoCon",
+    "Scout",
+    "Robo-Dozer",
+    "Evacuation Transport",
+    "Repair Vehicle",
+    "Earthworker",
+    "Small Capacity Air Transport",
+    "Tube",
+    "Wall",
+    "Lava Wall",
+    "Microbe Wall",
+    "Common Ore Mine",
+    "Rare Ore Mine",
+    "Guard Post",
+    "Light Tower",
+    "Common Storage",
+    "Rare Storage",
+    "Forum",
+    "Command Center",
+    "MHD Generator",
+    "Residence",
+    "Robot Command",
+    "Trade Center",
+    "Basic Lab",
+    "Medical Center",
+    "Nursery",
+    "Solar Power Array",
+    "Recreation Facility",
+    "University",
+    "Agridome",
+    "DIRT",
+    "Garage",
+    "Magma Well",
+    "Meteor Defense",
+    "Geothermal Plant",
+    "Arachnid Factory",
+    "Consumer Factory",
+    "Structure Factory",
+    "Vehicle Factory",
+    "Standard Lab",
+    "Advanced Lab",
+    "Observatory",
+    "Reinforced Residence",
+    "Advanced Residence",
+    "Common Ore Smelter",
+    "Spaceport",
+    "Rare Ore Smelter",
+    "GORF",
+    "Tokamak",
+];
+
+/// Display name of a unit type, for the types that occupy tiles
+pub fn unit_type_name(unit_type: u8) -> Option<&'static str> {
+    match unit_type {
+        0 => None,
+        _ => UNIT_TYPE_NAMES.get(unit_type as usize).copied(),
+    }
+}
+
+/// A unit in a saved game
+///
+/// Units are located through the unit index stored in each tile word they
+/// cover, so buildings report their whole footprint. The owner and type are
+/// decoded from the unit table record when there is one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unit {
+    /// Index into the game's unit table
+    pub index: u16,
+    /// Top-left tile covered by the unit
+    pub min: Position,
+    /// Bottom-right tile covered by the unit
+    pub max: Position,
+    /// Raw unit table record, if the saved game's unit table could be read
+    #[serde(default, with = "super::json::hex_option")]
+    pub record: Option<Vec<u8>>,
+}
+
+impl Unit {
+    /// Type of the unit, if its record holds a vehicle or structure type
+    pub fn unit_type(&self) -> Option<u8> {
+        let record = self.full_record()?;
+        let unit_type = u32::from_le_bytes(
+            record[UNIT_RECORD_TYPE_OFFSET..UNIT_RECORD_TYPE_OFFSET + 4]
+                .try_into()
+                .ok()?,
+        );
+        let unit_type = u8::try_from(unit_type).ok()?;
+        unit_type_name(unit_type).map(|_| unit_type)
+    }
+
+    /// Player owning the unit, from 0 to [`GAIA_PLAYER`]
+    pub fn owner(&self) -> Option<u8> {
+        let owner = self.full_record()?[UNIT_RECORD_OWNER_OFFSET] & 0x0F;
+        (owner < MAX_PLAYERS).then_some(owner)
+    }
+
+    /// The record, if it has the layout of the released game's unit records
+    fn full_record(&self) -> Option<&[u8]> {
+        self.record
+            .as_deref()
+            .filter(|record| record.len() == UNIT_RECORD_SIZE)
+    }
+
+    /// Number of tiles covered by the unit's bounding box
+    pub fn footprint(&self) -> (u32, u32) {
+        (
+            (self.max.x - self.min.x + 1) as u32,
+            (self.max.y - self.min.y + 1) as u32,
+        )
     }
 }
 
+/// Per-cell flags for formats that store them outside the tile word
+const CELL_WRECKAGE: u8 = 1 << 0;
+const CELL_UNIT: u8 = 1 << 1;
+
+/// Largest width or height a [`Map`] can be created with
+///
+/// Outpost 2 maps are at most 512 tiles wide, and no retail map is taller.
+pub const MAX_MAP_DIMENSION: u32 = 512;
+
 /// Complete map data
-#[derive(Debug, Clone)]
+///
+/// Cells are stored as packed tile words in a single row-major array. Rows are
+/// padded to a power-of-two stride so a cell's index is `(y << lg_stride) | x`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "MapDocument", try_from = "MapDocument")]
 pub struct Map {
+    /// Map metadata; changing the dimensions here doesn't resize the cells
     pub info: MapInfo,
-    pub cells: Vec<Vec<Cell>>,
+    /// Dimensions the cell storage was allocated with
+    width: u32,
+    height: u32,
+    lg_stride: u32,
+    tiles: Vec<TileWord>,
+    /// Cell heights, only allocated for formats that have them
+    heights: Vec<u8>,
+    /// `CELL_*` flags, only allocated for formats that have them
+    cell_flags: Vec<u8>,
+    /// Original cells of maps loaded from FORM2, empty otherwise
+    form2_cells: Vec<Option<Form2Cell>>,
+    pub clip_rect: ClipRect,
+    pub tileset_sources: Vec<TilesetSource>,
+    pub tile_mappings: Vec<TileMapping>,
+    pub terrain_types: Vec<TerrainType>,
+    pub tile_groups: Vec<TileGroup>,
+    /// Unknown value stored after the tile group count
+    pub tile_groups_unknown: u32,
+    /// Units found in a saved game, sorted by index; empty for plain maps
+    pub units: Vec<Unit>,
     pub tileset_cache: Option<Arc<crate::map::loader::TilesetCache>>,
 }
 
 impl Map {
+    /// Creates a map with every cell cleared
+    ///
+    /// # Panics
+    ///
+    /// Panics if the width or height is above [`MAX_MAP_DIMENSION`]. Loaders
+    /// must check the dimensions they read before calling this.
     pub fn new(info: MapInfo) -> Self {
-        let cells =
-            vec![
-                vec![Cell::new(Position::new(0, 0), CellType::Normal, 0); info.width as usize];
-                info.height as usize
-            ];
+        assert!(
+            info.width <= MAX_MAP_DIMENSION && info.height <= MAX_MAP_DIMENSION,
+            "map size {}x{} is above the {} tile limit",
+            info.width,
+            info.height,
+            MAX_MAP_DIMENSION
+        );
+        let lg_stride = info.width.next_power_of_two().trailing_zeros();
+        let tiles = vec![TileWord::default(); (info.height as usize) << lg_stride];
         Self {
+            width: info.width,
+            height: info.height,
             info,
-            cells,
+            lg_stride,
+            tiles,
+            heights: Vec::new(),
+            cell_flags: Vec::new(),
+            form2_cells: Vec::new(),
+            clip_rect: ClipRect::default(),
+            tileset_sources: Vec::new(),
+            tile_mappings: Vec::new(),
+            terrain_types: Vec::new(),
+            tile_groups: Vec::new(),
+            tile_groups_unknown: 0,
+            units: Vec::new(),
             tileset_cache: None,
         }
     }
 
-    pub fn get_cell(&self, x: i32, y: i32) -> Option<&Cell> {
-        if x < 0 || y < 0 {
+    /// Index of a cell in the tile array, or `None` if it is outside the map
+    fn cell_index(&self, x: i32, y: i32) -> Option<usize> {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
             return None;
         }
-        self.cells
-            .get(y as usize)
-            .and_then(|row| row.get(x as usize))
+        Some(((y as usize) << self.lg_stride) | x as usize)
     }
 
-    pub fn get_cell_mut(&mut self, x: i32, y: i32) -> Option<&mut Cell> {
-        if x < 0 || y < 0 {
-            return None;
+    pub fn get_cell(&self, x: i32, y: i32) -> Option<Cell> {
+        let index = self.cell_index(x, y)?;
+        let tile = self.tiles[index];
+        let flags = self.cell_flags.get(index).copied().unwrap_or(0);
+        Some(Cell {
+            position: Position::new(x, y),
+            tile,
+            height: self.heights.get(index).copied().unwrap_or(0),
+            has_wreckage: flags & CELL_WRECKAGE != 0,
+            has_unit: tile.unit_index() != 0 || flags & CELL_UNIT != 0,
+            form2: self.form2_cells.get(index).copied().flatten(),
+        })
+    }
+
+    /// The tile word of a cell
+    pub fn tile(&self, x: i32, y: i32) -> Option<TileWord> {
+        self.cell_index(x, y).map(|index| self.tiles[index])
+    }
+
+    /// Mutable access to the tile word of a cell
+    pub fn tile_mut(&mut self, x: i32, y: i32) -> Option<&mut TileWord> {
+        let index = self.cell_index(x, y)?;
+        Some(&mut self.tiles[index])
+    }
+
+    /// Sets the details that some formats store outside the tile word
+    pub fn set_cell_details(
+        &mut self,
+        x: i32,
+        y: i32,
+        height: u8,
+        has_wreckage: bool,
+        has_unit: bool,
+    ) {
+        let Some(index) = self.cell_index(x, y) else {
+            return;
+        };
+        if self.heights.is_empty() {
+            self.heights = vec![0; self.tiles.len()];
+            self.cell_flags = vec![0; self.tiles.len()];
         }
-        self.cells
-            .get_mut(y as usize)
-            .and_then(|row| row.get_mut(x as usize))
+        self.heights[index] = height;
+        self.cell_flags[index] =
+            (if has_wreckage { CELL_WRECKAGE } else { 0 }) | (if has_unit { CELL_UNIT } else { 0 });
+    }
+
+    /// Records the FORM2 cell a cell was loaded from
+    pub fn set_form2_cell(&mut self, x: i32, y: i32, cell: Form2Cell) {
+        let Some(index) = self.cell_index(x, y) else {
+            return;
+        };
+        if self.form2_cells.is_empty() {
+            self.form2_cells = vec![None; self.tiles.len()];
+        }
+        self.form2_cells[index] = Some(cell);
+    }
+
+    /// Whether cell heights and flags were set through [`Map::set_cell_details`]
+    pub fn has_cell_details(&self) -> bool {
+        !self.heights.is_empty()
+    }
+
+    /// Iterates over all cells in row-major order
+    pub fn cells(&self) -> impl Iterator<Item = Cell> + '_ {
+        (0..self.height as i32)
+            .flat_map(move |y| (0..self.width as i32).filter_map(move |x| self.get_cell(x, y)))
+    }
+
+    /// Name of the tileset in the given slot, if the slot is in use
+    pub fn tileset_name(&self, tileset_index: u16) -> Option<&str> {
+        self.tileset_sources
+            .get(tileset_index as usize)
+            .map(|source| source.filename.as_str())
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Resolves the tileset graphic for a tile word through the tile mapping table
+    pub fn tile_info(&self, tile: TileWord) -> Option<TileInfo<'_>> {
+        let mapping = self.tile_mappings.get(tile.tile_mapping_index() as usize)?;
+        let tileset_name = self.tileset_name(mapping.tileset_index)?;
+        Some(TileInfo {
+            tileset_index: mapping.tileset_index,
+            tileset_name,
+            tile_index: mapping.tile_graphic_index as u32,
+            mapping,
+        })
+    }
+
+    /// Looks up a unit by its index in the unit table
+    pub fn unit(&self, index: u16) -> Option<&Unit> {
+        self.units
+            .binary_search_by_key(&index, |unit| unit.index)
+            .ok()
+            .map(|i| &self.units[i])
     }
 
     pub fn set_tileset_cache(&mut self, cache: Arc<crate::map::loader::TilesetCache>) {
         self.tileset_cache = Some(cache);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every field of a tile word, in bit order
+    fn fields(word: TileWord) -> (u8, u16, u16, [bool; 5]) {
+        (
+            word.cell_type_index(),
+            word.tile_mapping_index(),
+            word.unit_index(),
+            [
+                word.is_lava(),
+                word.is_lava_possible(),
+                word.is_expand(),
+                word.is_microbe(),
+                word.is_wall_or_building(),
+            ],
+        )
+    }
+
+    #[test]
+    fn tile_word_unpacks_each_field() {
+        // Each field set to a distinct value, with the flags alternating
+        let raw = 0x13 | 0x5A5 << 5 | 0x3C3 << 16 | 1 << 27 | 1 << 29 | 1 << 31;
+        assert_eq!(
+            fields(TileWord(raw)),
+            (0x13, 0x5A5, 0x3C3, [true, false, true, false, true])
+        );
+
+        let flags = TileWord(0b01010 << 27);
+        assert_eq!(fields(flags), (0, 0, 0, [false, true, false, true, false]));
+    }
+
+    #[test]
+    fn tile_word_packs_each_field() {
+        let mut word = TileWord::default();
+        word.set_cell_type_index(0x1F);
+        assert_eq!(word.raw(), 0x1F);
+        word.set_tile_mapping_index(0x7FF);
+        assert_eq!(word.raw(), 0xFFFF);
+        word.set_unit_index(0x7FF);
+        assert_eq!(word.raw(), 0x07FF_FFFF);
+
+        type Setter = fn(&mut TileWord, bool);
+        let setters: [(Setter, u32); 5] = [
+            (TileWord::set_lava, 27),
+            (TileWord::set_lava_possible, 28),
+            (TileWord::set_expand, 29),
+            (TileWord::set_microbe, 30),
+            (TileWord::set_wall_or_building, 31),
+        ];
+        for (set, bit) in setters {
+            let mut word = TileWord::default();
+            set(&mut word, true);
+            assert_eq!(word.raw(), 1 << bit, "bit {}", bit);
+            set(&mut word, false);
+            assert_eq!(word.raw(), 0, "bit {}", bit);
+        }
+    }
+
+    #[test]
+    fn tile_word_setters_leave_other_fields_alone() {
+        let mut word = TileWord(u32::MAX);
+        word.set_tile_mapping_index(0);
+        assert_eq!(fields(word), (0x1F, 0, 0x7FF, [true; 5]));
+        word.set_unit_index(0);
+        word.set_cell_type_index(0);
+        assert_eq!(word.raw(), 0xF800_0000);
+        word.set_lava(false);
+        word.set_microbe(false);
+        assert_eq!(fields(word), (0, 0, 0, [false, true, true, false, true]));
+    }
+
+    #[test]
+    fn tile_word_new_sets_only_type_and_mapping() {
+        let word = TileWord::new(CellType::Tube3.index(), 1234);
+        assert_eq!(word.cell_type(), CellType::Tube3);
+        assert_eq!(fields(word), (CellType::Tube3.index(), 1234, 0, [false; 5]));
+    }
+
+    #[test]
+    fn cell_lookups_stay_within_the_allocated_cells() {
+        let mut map = Map::new(MapInfo {
+            width: 32,
+            height: 4,
+            ..Default::default()
+        });
+        map.info.width = 64;
+        map.info.height = 8;
+
+        assert!(map.get_cell(31, 3).is_some());
+        assert_eq!(map.get_cell(32, 0), None);
+        assert_eq!(map.tile(0, 4), None);
+        assert!(map.tile_mut(63, 7).is_none());
+        assert_eq!(map.cells().count(), 32 * 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "above the 512 tile limit")]
+    fn new_rejects_dimensions_above_the_limit() {
+        Map::new(MapInfo {
+            width: 32,
+            height: MAX_MAP_DIMENSION + 1,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn tile_mapping_graphics_cover_every_frame() {
+        let mapping = TileMapping {
+            tileset_index: 0,
+            tile_graphic_index: 40,
+            animation_count: 3,
+            animation_delay: 5,
+        };
+        assert_eq!(mapping.graphics().collect::<Vec<_>>(), [40, 41, 42, 43]);
+
+        let still = TileMapping {
+            animation_count: 0,
+            ..mapping
+        };
+        assert_eq!(still.graphics().collect::<Vec<_>>(), [40]);
+    }
+}
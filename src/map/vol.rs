@@ -0,0 +1,444 @@
+//! Reading of Outpost 2 VOL archives
+//!
+//! A VOL file is a sequence of tagged sections, each an ASCII tag followed by
+//! a 32-bit length whose top bit is a flag rather than part of the length:
+//!
+//! ```text
+//! "VOL " <len>
+//!   "volh" <len>  header (normally empty)
+//!   "vols" <len>  u32 string table size, then null-terminated file names
+//!   "voli" <len>  14-byte index entries
+//! "VBLK" <len>  file data, one block per file
+//! ```
+//!
+//! Entries are stored as is or compressed with LZH (see [`super::lzh`]). The
+//! format also defines RLE and LZ compression, which aren't supported.
+//!
+//! Paths inside an archive are written as `archive.vol:file.ext`, for example
+//! `maps.vol:on6_01.map`.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use super::loader::MapLoadError;
+use super::lzh;
+
+const VOL_TAG: &[u8; 4] = b"VOL ";
+const VOLH_TAG: &[u8; 4] = b"volh";
+const VOLS_TAG: &[u8; 4] = b"vols";
+const VOLI_TAG: &[u8; 4] = b"voli";
+const VBLK_TAG: &[u8; 4] = b"VBLK";
+
+/// The top bit of a section length is a flag, not part of the length
+const SECTION_LENGTH_MASK: u32 = 0x7FFF_FFFF;
+
+/// Size of an entry in the "voli" index section
+const INDEX_ENTRY_SIZE: usize = 14;
+
+/// Compression applied to a file stored in a VOL archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolCompression {
+    None,
+    Rle,
+    Lz,
+    Lzh,
+    Unknown(u16),
+}
+
+impl VolCompression {
+    fn from_raw(value: u16) -> Self {
+        match value {
+            0x100 => VolCompression::None,
+            0x101 => VolCompression::Rle,
+            0x102 => VolCompression::Lz,
+            0x103 => VolCompression::Lzh,
+            other => VolCompression::Unknown(other),
+        }
+    }
+}
+
+/// A file stored in a VOL archive
+#[derive(Debug, Clone)]
+pub struct VolEntry {
+    pub name: String,
+    /// Offset of the entry's "VBLK" block from the start of the archive
+    pub offset: u32,
+    pub size: u32,
+    pub compression: VolCompression,
+}
+
+/// An open VOL archive
+///
+/// Only the index is kept in memory; file contents are read on demand.
+#[derive(Debug, Clone)]
+pub struct VolArchive {
+    path: PathBuf,
+    entries: Vec<VolEntry>,
+}
+
+impl VolArchive {
+    /// Opens an archive and reads its index
+    pub fn open(path: &Path) -> Result<Self, MapLoadError> {
+        let mut file = File::open(path)?;
+
+        read_section_header(&mut file, VOL_TAG)?;
+        let header_len = read_section_header(&mut file, VOLH_TAG)?;
+        skip_padded(&mut file, header_len)?;
+
+        let strings_len = read_section_header(&mut file, VOLS_TAG)?;
+        let strings = read_section_body(&mut file, strings_len, "String table")?;
+        align_to_section(&mut file, strings_len)?;
+        // The section begins with the size of the string data actually used
+        let table_len = strings
+            .get(0..4)
+            .map(|len| u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize)
+            .ok_or_else(|| vol_error("String table is missing its length"))?;
+        let string_table = &strings[4..(4 + table_len).min(strings.len())];
+
+        let index_len = read_section_header(&mut file, VOLI_TAG)?;
+        let index = read_section_body(&mut file, index_len, "Index")?;
+
+        let entries = index
+            .chunks_exact(INDEX_ENTRY_SIZE)
+            .filter_map(|raw| {
+                let field = |at: usize| {
+                    u32::from_le_bytes([raw[at], raw[at + 1], raw[at + 2], raw[at + 3]])
+                };
+                let name_offset = field(0) as usize;
+                let offset = field(4);
+                let size = field(8);
+                let compression = VolCompression::from_raw(u16::from_le_bytes([raw[12], raw[13]]));
+
+                // Unused index slots point past the string table
+                let name_bytes = string_table.get(name_offset..)?;
+                let name_len = name_bytes.iter().position(|&b| b == 0)?;
+                if name_len == 0 || offset == 0 {
+                    return None;
+                }
+                Some(VolEntry {
+                    name: String::from_utf8_lossy(&name_bytes[..name_len]).into_owned(),
+                    offset,
+                    size,
+                    compression,
+                })
+            })
+            .collect();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            entries,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn entries(&self) -> &[VolEntry] {
+        &self.entries
+    }
+
+    /// Looks up an entry by name, ignoring case like the game does
+    pub fn find(&self, name: &str) -> Option<&VolEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Reads the contents of a file in the archive
+    pub fn read(&self, name: &str) -> Result<Vec<u8>, MapLoadError> {
+        let entry = self
+            .find(name)
+            .ok_or_else(|| vol_error(format!("{} not found in {}", name, self.path.display())))?;
+        self.read_entry(entry)
+    }
+
+    /// Reads the contents of an entry returned by [`VolArchive::entries`],
+    /// decompressing LZH entries
+    pub fn read_entry(&self, entry: &VolEntry) -> Result<Vec<u8>, MapLoadError> {
+        if !matches!(
+            entry.compression,
+            VolCompression::None | VolCompression::Lzh
+        ) {
+            return Err(vol_error(format!(
+                "{} uses unsupported compression {:?}",
+                entry.name, entry.compression
+            )));
+        }
+
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(entry.offset as u64))?;
+        let block_len = read_section_header(&mut file, VBLK_TAG)?;
+        if block_len < entry.size {
+            return Err(vol_error(format!(
+                "Data block for {} is {} bytes, expected {}",
+                entry.name, block_len, entry.size
+            )));
+        }
+
+        let data = read_section_body(&mut file, entry.size, &entry.name)?;
+        match entry.compression {
+            VolCompression::Lzh => Ok(lzh::decompress(&data)),
+            _ => Ok(data),
+        }
+    }
+}
+
+/// Splits a path of the form `archive.vol:file` into the archive path and file name
+pub fn split_vol_path(path: &Path) -> Option<(PathBuf, String)> {
+    let text = path.to_str()?;
+    let (archive, name) = text.rsplit_once(':')?;
+    if !is_vol_file(Path::new(archive)) || name.is_empty() {
+        return None;
+    }
+    Some((PathBuf::from(archive), name.to_string()))
+}
+
+/// Builds an `archive.vol:file` path
+pub fn join_vol_path(archive: &Path, name: &str) -> PathBuf {
+    PathBuf::from(format!("{}:{}", archive.display(), name))
+}
+
+/// Returns true if the path names a VOL archive itself
+pub fn is_vol_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("vol"))
+}
+
+/// Reads a file from disk, or from inside a VOL archive for `archive.vol:file` paths
+pub fn read_path(path: &Path) -> Result<Vec<u8>, MapLoadError> {
+    match split_vol_path(path) {
+        Some((archive, name)) => VolArchive::open(&archive)?.read(&name),
+        None => Ok(std::fs::read(path)?),
+    }
+}
+
+fn vol_error(message: impl Into<String>) -> MapLoadError {
+    MapLoadError::VolError(message.into())
+}
+
+/// Reads a section header, checks its tag and returns the section length
+fn read_section_header<R: Read>(reader: &mut R, tag: &[u8; 4]) -> Result<u32, MapLoadError> {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != tag {
+        return Err(vol_error(format!(
+            "Expected section {:?}, found {:?}",
+            String::from_utf8_lossy(tag),
+            String::from_utf8_lossy(&header[0..4])
+        )));
+    }
+    let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    Ok(length & SECTION_LENGTH_MASK)
+}
+
+/// Reads `len` bytes of a section body
+///
+/// The length is checked against what is left of the file first, so a
+/// corrupt header can't make us allocate gigabytes before the read fails.
+fn read_section_body<R: Read + Seek>(
+    reader: &mut R,
+    len: u32,
+    what: &str,
+) -> Result<Vec<u8>, MapLoadError> {
+    let position = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(position))?;
+    let remaining = end.saturating_sub(position);
+    if len as u64 > remaining {
+        return Err(vol_error(format!(
+            "{} is {} bytes but only {} remain in the archive",
+            what, len, remaining
+        )));
+    }
+
+    let mut body = vec![0u8; len as usize];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+/// Skips a section body including its padding
+fn skip_padded<R: Seek>(reader: &mut R, len: u32) -> Result<(), MapLoadError> {
+    reader.seek(SeekFrom::Current(len as i64))?;
+    align_to_section(reader, len)
+}
+
+/// Sections start on 4-byte boundaries; skips the padding after a body of `len` bytes
+fn align_to_section<R: Seek>(reader: &mut R, len: u32) -> Result<(), MapLoadError> {
+    let padding = (4 - len % 4) % 4;
+    reader.seek(SeekFrom::Current(padding as i64))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::lzh::tests::{encode, Token};
+    use std::io::Cursor;
+
+    /// Flag set on every section length written by the game's tools
+    const LENGTH_FLAG: u32 = 0x8000_0000;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("op2mapviewer-{}-{}", std::process::id(), name))
+    }
+
+    fn section(tag: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = tag.to_vec();
+        data.extend((body.len() as u32 | LENGTH_FLAG).to_le_bytes());
+        data.extend(body);
+        data.resize(data.len().next_multiple_of(4), 0);
+        data
+    }
+
+    /// Builds an archive holding the given (name, compression, stored bytes) files
+    fn vol_archive(files: &[(&str, u16, &[u8])]) -> Vec<u8> {
+        let mut names = Vec::new();
+        let mut name_offsets = Vec::new();
+        for (name, _, _) in files {
+            name_offsets.push(names.len() as u32);
+            names.extend(name.as_bytes());
+            names.push(0);
+        }
+        let mut strings = (names.len() as u32).to_le_bytes().to_vec();
+        strings.extend(&names);
+
+        let index_len = (files.len() * INDEX_ENTRY_SIZE) as u32;
+        // "VOL ", "volh" and the headers of "vols" and "voli"
+        let header_len = 8 + 8 + section(VOLS_TAG, &strings).len() + 8;
+        let mut offset = header_len + (index_len as usize).next_multiple_of(4);
+
+        let mut index = Vec::new();
+        let mut blocks = Vec::new();
+        for ((_, compression, data), name_offset) in files.iter().zip(name_offsets) {
+            index.extend(name_offset.to_le_bytes());
+            index.extend((offset as u32).to_le_bytes());
+            index.extend((data.len() as u32).to_le_bytes());
+            index.extend(compression.to_le_bytes());
+            let block = section(VBLK_TAG, data);
+            offset += block.len();
+            blocks.extend(block);
+        }
+
+        let mut body = section(VOLH_TAG, &[]);
+        body.extend(section(VOLS_TAG, &strings));
+        body.extend(section(VOLI_TAG, &index));
+        let mut archive = section(VOL_TAG, &body);
+        archive.extend(blocks);
+        archive
+    }
+
+    #[test]
+    fn reads_index_with_flagged_section_lengths() {
+        let path = temp_path("index.vol");
+        std::fs::write(
+            &path,
+            vol_archive(&[
+                ("ON6_01.MAP", 0x100, b"map"),
+                ("well0000.bmp", 0x103, &[0; 5]),
+            ]),
+        )
+        .unwrap();
+
+        let archive = VolArchive::open(&path).unwrap();
+        let entries: Vec<_> = archive
+            .entries()
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.size, entry.compression))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("ON6_01.MAP", 3, VolCompression::None),
+                ("well0000.bmp", 5, VolCompression::Lzh)
+            ]
+        );
+        assert_eq!(archive.find("on6_01.map").unwrap().name, "ON6_01.MAP");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_stored_and_lzh_entries() {
+        let mut tokens: Vec<_> = b"tile".iter().map(|&byte| Token::Literal(byte)).collect();
+        tokens.push(Token::Match {
+            distance: 4,
+            length: 8,
+        });
+        let compressed = encode(&tokens);
+
+        let path = temp_path("entries.vol");
+        std::fs::write(
+            &path,
+            vol_archive(&[
+                ("stored.txt", 0x100, b"plain contents"),
+                ("packed.txt", 0x103, &compressed),
+                ("rle.txt", 0x101, b"x"),
+            ]),
+        )
+        .unwrap();
+
+        let archive = VolArchive::open(&path).unwrap();
+        assert_eq!(archive.read("stored.txt").unwrap(), b"plain contents");
+        assert_eq!(archive.read("packed.txt").unwrap(), b"tiletiletile");
+        assert!(matches!(
+            archive.read("rle.txt"),
+            Err(MapLoadError::VolError(_))
+        ));
+        assert!(matches!(
+            archive.read("missing.txt"),
+            Err(MapLoadError::VolError(_))
+        ));
+
+        // The same entries through an `archive.vol:file` path
+        assert_eq!(
+            read_path(&join_vol_path(&path, "PACKED.TXT")).unwrap(),
+            b"tiletiletile"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn splits_archive_paths() {
+        assert_eq!(
+            split_vol_path(Path::new("maps.vol:on6_01.map")),
+            Some((PathBuf::from("maps.vol"), "on6_01.map".to_string()))
+        );
+        assert_eq!(
+            split_vol_path(Path::new("C:\\Outpost2\\MAPS.VOL:eden01.map")),
+            Some((
+                PathBuf::from("C:\\Outpost2\\MAPS.VOL"),
+                "eden01.map".to_string()
+            ))
+        );
+        // Not an archive, no file name, or a plain drive-letter path
+        assert_eq!(split_vol_path(Path::new("maps.zip:on6_01.map")), None);
+        assert_eq!(split_vol_path(Path::new("maps.vol:")), None);
+        assert_eq!(split_vol_path(Path::new("C:\\maps\\on6_01.map")), None);
+        assert_eq!(split_vol_path(Path::new("on6_01.map")), None);
+
+        let joined = join_vol_path(Path::new("art.vol"), "well0001.bmp");
+        assert_eq!(
+            split_vol_path(&joined),
+            Some((PathBuf::from("art.vol"), "well0001.bmp".to_string()))
+        );
+    }
+
+    #[test]
+    fn reads_section_bodies_that_fit() {
+        let mut reader = Cursor::new(b"headbody".to_vec());
+        reader.seek(SeekFrom::Start(4)).unwrap();
+        assert_eq!(read_section_body(&mut reader, 4, "Body").unwrap(), b"body");
+    }
+
+    #[test]
+    fn rejects_section_lengths_past_the_end_of_the_file() {
+        let mut reader = Cursor::new(b"headbody".to_vec());
+        reader.seek(SeekFrom::Start(4)).unwrap();
+        let err = read_section_body(&mut reader, SECTION_LENGTH_MASK, "Index").unwrap_err();
+        assert!(matches!(err, MapLoadError::VolError(_)), "{}", err);
+        // The reader is left where it was
+        assert_eq!(reader.position(), 4);
+    }
+}
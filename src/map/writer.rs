@@ -0,0 +1,253 @@
+//! Writing of maps in the native Outpost 2 `.map` layout
+//!
+//! The writer mirrors [`super::loader`]: every section the loader reads is
+//! written back in the same order and encoding, so loading and saving an
+//! unmodified map reproduces the original file byte for byte.
+
+use std::io::Write;
+use std::path::Path;
+
+use super::format::FormatRegistry;
+use super::loader::{tile_word_index, MapLoadError, LG_WIDTH_RANGE, MAP_VERSION_TAG, TILESET_TAG};
+use super::types::{Map, MappingRange, TerrainType, TileGroup, TileMapping, TilesetSource};
+
+/// Saves a map to the given file path
+///
+/// The format is chosen from the file extension, defaulting to the native
+/// `.map` layout.
+pub fn save_map(map: &Map, file_path: &Path) -> Result<(), MapLoadError> {
+    FormatRegistry::default().save(map, file_path).map(|_| ())
+}
+
+/// Serialises a map in the native `.map` layout
+pub fn write_map<W: Write>(map: &Map, writer: &mut W) -> Result<(), MapLoadError> {
+    // The unit table and player data of saved games aren't kept in memory
+    if map.info.is_saved_game {
+        return Err(MapLoadError::InvalidFormat(
+            "Saved games can't be written as maps".into(),
+        ));
+    }
+
+    let width = map.info.width;
+    let height = map.info.height;
+    let lg_width = width.trailing_zeros();
+    if !width.is_power_of_two() || !LG_WIDTH_RANGE.contains(&lg_width) || height == 0 {
+        return Err(MapLoadError::InvalidFormat(format!(
+            "Map size {}x{} can't be stored in a map file",
+            width, height
+        )));
+    }
+
+    // Maps converted from other formats have no version tag of their own
+    let version_tag = map.info.version_tag.max(MAP_VERSION_TAG);
+
+    write_u32(writer, version_tag)?;
+    write_u32(writer, 0)?;
+    write_u32(writer, lg_width)?;
+    write_u32(writer, height)?;
+    write_u32(writer, map.tileset_sources.len() as u32)?;
+
+    write_tile_words(map, writer)?;
+
+    write_i32(writer, map.clip_rect.x1)?;
+    write_i32(writer, map.clip_rect.y1)?;
+    write_i32(writer, map.clip_rect.x2)?;
+    write_i32(writer, map.clip_rect.y2)?;
+
+    for source in &map.tileset_sources {
+        write_tileset_source(writer, source)?;
+    }
+
+    writer.write_all(TILESET_TAG)?;
+
+    write_u32(writer, map.tile_mappings.len() as u32)?;
+    for mapping in &map.tile_mappings {
+        write_tile_mapping(writer, mapping)?;
+    }
+
+    write_u32(writer, map.terrain_types.len() as u32)?;
+    for terrain in &map.terrain_types {
+        write_terrain_type(writer, terrain)?;
+    }
+
+    write_u32(writer, version_tag)?;
+    write_u32(writer, version_tag)?;
+    write_u32(writer, map.tile_groups.len() as u32)?;
+    write_u32(writer, map.tile_groups_unknown)?;
+    for group in &map.tile_groups {
+        write_tile_group(writer, group)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the tile words in file order (vertical strips of 32 columns)
+fn write_tile_words<W: Write>(map: &Map, writer: &mut W) -> Result<(), MapLoadError> {
+    let width = map.info.width;
+    let height = map.info.height;
+    let mut tiles = vec![0u32; width as usize * height as usize];
+    for y in 0..height {
+        for x in 0..width {
+            let tile = map.tile(x as i32, y as i32).ok_or_else(|| {
+                MapLoadError::InvalidFormat(format!("Map is missing cell ({}, {})", x, y))
+            })?;
+            tiles[tile_word_index(x, y, height)] = tile.raw();
+        }
+    }
+
+    let bytes: Vec<u8> = tiles.iter().flat_map(|tile| tile.to_le_bytes()).collect();
+    writer.write_all(&bytes)?;
+    Ok(())
+}
+
+fn write_tileset_source<W: Write>(
+    writer: &mut W,
+    source: &TilesetSource,
+) -> Result<(), MapLoadError> {
+    write_sized_string(writer, source.filename.as_bytes())?;
+    // Empty slots don't store a tile count
+    if !source.filename.is_empty() {
+        write_u32(writer, source.num_tiles)?;
+    }
+    Ok(())
+}
+
+fn write_tile_mapping<W: Write>(writer: &mut W, mapping: &TileMapping) -> Result<(), MapLoadError> {
+    write_u16(writer, mapping.tileset_index)?;
+    write_u16(writer, mapping.tile_graphic_index)?;
+    write_u16(writer, mapping.animation_count)?;
+    write_u16(writer, mapping.animation_delay)?;
+    Ok(())
+}
+
+fn write_mapping_range<W: Write>(writer: &mut W, range: &MappingRange) -> Result<(), MapLoadError> {
+    write_u16(writer, range.start)?;
+    write_u16(writer, range.end)?;
+    Ok(())
+}
+
+fn write_terrain_type<W: Write>(writer: &mut W, terrain: &TerrainType) -> Result<(), MapLoadError> {
+    write_mapping_range(writer, &terrain.tile_mapping_range)?;
+    write_u16(writer, terrain.bulldozed_mapping)?;
+    write_u16(writer, terrain.rubble_mapping)?;
+    write_u16_array(writer, &terrain.tube_mappings)?;
+    for wall in &terrain.wall_mappings {
+        write_u16_array(writer, wall)?;
+    }
+    write_u16(writer, terrain.lava_mapping)?;
+    write_u16_array(writer, &terrain.flat)?;
+    write_u16_array(writer, &terrain.tube_connection_mappings)?;
+    write_u16(writer, terrain.scorched_mapping)?;
+    for range in &terrain.scorched_ranges {
+        write_mapping_range(writer, range)?;
+    }
+    write_u16_array(writer, &terrain.unknown)?;
+    Ok(())
+}
+
+fn write_tile_group<W: Write>(writer: &mut W, group: &TileGroup) -> Result<(), MapLoadError> {
+    if group.mapping_indices.len() != (group.width * group.height) as usize {
+        return Err(MapLoadError::InvalidFormat(format!(
+            "Tile group is {}x{} but has {} mappings",
+            group.width,
+            group.height,
+            group.mapping_indices.len()
+        )));
+    }
+    write_u32(writer, group.width)?;
+    write_u32(writer, group.height)?;
+    for &index in &group.mapping_indices {
+        write_u32(writer, index)?;
+    }
+    write_sized_string(writer, &group.name)?;
+    Ok(())
+}
+
+fn write_u16<W: Write>(writer: &mut W, value: u16) -> Result<(), MapLoadError> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> Result<(), MapLoadError> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_i32<W: Write>(writer: &mut W, value: i32) -> Result<(), MapLoadError> {
+    writer.write_all(&value.to_le_bytes())?;
+    Ok(())
+}
+
+fn write_u16_array<W: Write>(writer: &mut W, values: &[u16]) -> Result<(), MapLoadError> {
+    for &value in values {
+        write_u16(writer, value)?;
+    }
+    Ok(())
+}
+
+/// Writes a string prefixed by its 32-bit length
+fn write_sized_string<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<(), MapLoadError> {
+    write_u32(writer, bytes.len() as u32)?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::map::{load_map, Diagnostics};
+
+    fn sample_path() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("on6_01.map")
+    }
+
+    /// A path in the temp directory that no other test run uses
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("op2mapviewer-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn round_trips_native_map() {
+        let original = std::fs::read(sample_path()).unwrap();
+        let map = load_map(&sample_path(), &mut Diagnostics::new()).unwrap();
+
+        let mut written = Vec::new();
+        write_map(&map, &mut written).unwrap();
+        assert!(written == original, "written map differs from on6_01.map");
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let original = std::fs::read(sample_path()).unwrap();
+        let map = load_map(&sample_path(), &mut Diagnostics::new()).unwrap();
+
+        let json_path = temp_path("round_trip.json");
+        save_map(&map, &json_path).unwrap();
+        let reloaded = load_map(&json_path, &mut Diagnostics::new());
+        std::fs::remove_file(&json_path).unwrap();
+
+        let mut written = Vec::new();
+        write_map(&reloaded.unwrap(), &mut written).unwrap();
+        assert!(
+            written == original,
+            "map saved through JSON differs from on6_01.map"
+        );
+    }
+
+    #[test]
+    fn rejected_save_leaves_existing_file_alone() {
+        let mut map = load_map(&sample_path(), &mut Diagnostics::new()).unwrap();
+        map.info.is_saved_game = true;
+
+        let path = temp_path("saved_game.map");
+        std::fs::write(&path, b"existing").unwrap();
+        let result = save_map(&map, &path);
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(MapLoadError::InvalidFormat(_))));
+        assert_eq!(contents, b"existing");
+    }
+}
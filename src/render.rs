@@ -0,0 +1,644 @@
+//! Software rendering of maps to images
+//!
+//! Maps are composed from their tileset graphics on the CPU, so images can be
+//! produced without a window or GPU.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageFormat, ImageResult, Rgba, RgbaImage};
+
+use crate::map::types::{Cell, CellSpeed, CellType, Map, TileGroup};
+use crate::map::Tileset;
+
+/// Grid line color, matching the viewer's default
+const GRID_COLOR: Rgba<u8> = Rgba([128, 128, 128, 255]);
+
+/// Fill for tile group cells whose graphic isn't available
+const MISSING_TILE_COLOR: Rgba<u8> = Rgba([48, 48, 48, 255]);
+
+/// Opacity of overlays drawn over the tiles, out of 255
+pub const OVERLAY_ALPHA: u8 = 128;
+
+/// A layer of gameplay information drawn over the tiles
+///
+/// Each overlay tints the cells it applies to; flag overlays leave other
+/// cells alone. Overlays are drawn in the order of [`Overlay::ALL`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Overlay {
+    /// Colors each cell by its cell type
+    CellType,
+    /// Colors each cell by how fast units move over it
+    Passability,
+    LavaPossible,
+    Microbe,
+    WallOrBuilding,
+    /// Cells whose tile word refers to a unit
+    Units,
+    Expand,
+}
+
+impl Overlay {
+    pub const ALL: [Overlay; 7] = [
+        Overlay::CellType,
+        Overlay::Passability,
+        Overlay::LavaPossible,
+        Overlay::Microbe,
+        Overlay::WallOrBuilding,
+        Overlay::Units,
+        Overlay::Expand,
+    ];
+
+    /// Name shown in menus and legends
+    pub fn name(self) -> &'static str {
+        match self {
+            Overlay::CellType => "Cell types",
+            Overlay::Passability => "Passability",
+            Overlay::LavaPossible => "Lava possible",
+            Overlay::Microbe => "Microbe",
+            Overlay::WallOrBuilding => "Wall or building",
+            Overlay::Units => "Unit occupancy",
+            Overlay::Expand => "Expand flag",
+        }
+    }
+
+    /// Name used on the command line
+    pub fn key(self) -> &'static str {
+        match self {
+            Overlay::CellType => "celltype",
+            Overlay::Passability => "passability",
+            Overlay::LavaPossible => "lava-possible",
+            Overlay::Microbe => "microbe",
+            Overlay::WallOrBuilding => "wall",
+            Overlay::Units => "units",
+            Overlay::Expand => "expand",
+        }
+    }
+
+    /// Tint for a cell, or `None` if the overlay doesn't mark it
+    pub fn cell_rgb(self, cell: &Cell) -> Option<[u8; 3]> {
+        let tile = cell.tile;
+        let flag = |set: bool, rgb: [u8; 3]| set.then_some(rgb);
+        match self {
+            Overlay::CellType => Some(cell_type_rgb(cell.cell_type())),
+            Overlay::Passability => Some(speed_rgb(cell.cell_type().speed())),
+            Overlay::LavaPossible => flag(tile.is_lava_possible(), LAVA_POSSIBLE_RGB),
+            Overlay::Microbe => flag(tile.is_microbe(), MICROBE_RGB),
+            Overlay::WallOrBuilding => flag(tile.is_wall_or_building(), WALL_OR_BUILDING_RGB),
+            Overlay::Units => flag(tile.unit_index() != 0 || cell.has_unit, UNITS_RGB),
+            Overlay::Expand => flag(tile.is_expand(), EXPAND_RGB),
+        }
+    }
+
+    /// Colors the overlay uses and what they mean
+    pub fn legend(self) -> Vec<([u8; 3], &'static str)> {
+        match self {
+            Overlay::CellType => {
+                // Cliff and tube types share a color, so list them once
+                let mut entries: Vec<([u8; 3], &'static str)> = Vec::new();
+                for cell_type in CellType::ALL {
+                    let name = match cell_type {
+                        CellType::ZPad12
+                        | CellType::ZPad13
+                        | CellType::ZPad14
+                        | CellType::ZPad15
+                        | CellType::ZPad16
+                        | CellType::ZPad17
+                        | CellType::ZPad18
+                        | CellType::ZPad19
+                        | CellType::ZPad20 => continue,
+                        CellType::NorthCliffs
+                        | CellType::CliffsHighSide
+                        | CellType::CliffsLowSide => "Cliffs",
+                        CellType::Tube0
+                        | CellType::Tube1
+                        | CellType::Tube2
+                        | CellType::Tube3
+                        | CellType::Tube4
+                        | CellType::Tube5 => "Tubes",
+                        _ => cell_type.name(),
+                    };
+                    if !entries.iter().any(|&(_, existing)| existing == name) {
+                        entries.push((cell_type_rgb(cell_type), name));
+                    }
+                }
+                entries
+            }
+            Overlay::Passability => [
+                (CellSpeed::Fast, "Fast"),
+                (CellSpeed::Medium, "Medium"),
+                (CellSpeed::Slow, "Slow"),
+                (CellSpeed::Impassible, "Impassible"),
+            ]
+            .into_iter()
+            .map(|(speed, name)| (speed_rgb(speed), name))
+            .collect(),
+            Overlay::LavaPossible => vec![(LAVA_POSSIBLE_RGB, "Lava can spread here")],
+            Overlay::Microbe => vec![(MICROBE_RGB, "Blight present")],
+            Overlay::WallOrBuilding => vec![(WALL_OR_BUILDING_RGB, "Wall or building")],
+            Overlay::Units => vec![(UNITS_RGB, "Occupied by a unit")],
+            Overlay::Expand => vec![(EXPAND_RGB, "Expand flag set")],
+        }
+    }
+}
+
+impl FromStr for Overlay {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = name.to_ascii_lowercase();
+        if name == "cell-type" {
+            return Ok(Overlay::CellType);
+        }
+        Overlay::ALL
+            .into_iter()
+            .find(|overlay| overlay.key() == name)
+            .ok_or_else(|| format!("Unknown overlay: {}", name))
+    }
+}
+
+const LAVA_POSSIBLE_RGB: [u8; 3] = [255, 140, 0];
+const MICROBE_RGB: [u8; 3] = [60, 220, 60];
+const WALL_OR_BUILDING_RGB: [u8; 3] = [255, 255, 255];
+const UNITS_RGB: [u8; 3] = [255, 220, 0];
+const EXPAND_RGB: [u8; 3] = [180, 120, 255];
+
+/// Options for [`render_map`]
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Output pixels per tile pixel; tiles are 32 pixels at scale 1
+    pub scale: f32,
+    pub grid: bool,
+    /// Overlays to draw, in any order
+    pub overlays: Vec<Overlay>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            grid: false,
+            overlays: Vec::new(),
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Size of one map cell in the output image
+    pub fn tile_pixels(&self) -> u32 {
+        ((Tileset::DEFAULT_TILE_SIZE as f32 * self.scale).round() as u32).max(1)
+    }
+
+    /// Size of the rendered image, or `None` if it doesn't fit in 32 bits
+    pub fn output_size(&self, map: &Map) -> Option<(u32, u32)> {
+        let tile = self.tile_pixels();
+        Some((
+            map.info.width.checked_mul(tile)?,
+            map.info.height.checked_mul(tile)?,
+        ))
+    }
+}
+
+/// A rectangle of map cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CellRect {
+    /// The whole map
+    pub fn of_map(map: &Map) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width: map.info.width,
+            height: map.info.height,
+        }
+    }
+
+    /// Splits the rectangle into pieces of at most `max_cells` cells per side,
+    /// returned row by row along with their column and row numbers
+    pub fn split(self, max_cells: u32) -> Vec<(u32, u32, CellRect)> {
+        let max_cells = max_cells.max(1);
+        let mut pieces = Vec::new();
+        for (row, y) in (0..self.height).step_by(max_cells as usize).enumerate() {
+            for (column, x) in (0..self.width).step_by(max_cells as usize).enumerate() {
+                pieces.push((
+                    column as u32,
+                    row as u32,
+                    CellRect {
+                        x: self.x + x,
+                        y: self.y + y,
+                        width: max_cells.min(self.width - x),
+                        height: max_cells.min(self.height - y),
+                    },
+                ));
+            }
+        }
+        pieces
+    }
+}
+
+/// Renders the whole map into an image
+///
+/// Cells whose tile can't be found in the map's tileset cache are filled with
+/// their cell type color instead.
+pub fn render_map(map: &Map, options: &RenderOptions) -> RgbaImage {
+    MapRenderer::new(map, options).render_region(CellRect::of_map(map))
+}
+
+/// Renders parts of a map, reusing resized tile graphics between calls
+pub struct MapRenderer<'a> {
+    map: &'a Map,
+    options: RenderOptions,
+    tiles: ScaledTiles<'a>,
+}
+
+impl<'a> MapRenderer<'a> {
+    pub fn new(map: &'a Map, options: &RenderOptions) -> Self {
+        Self {
+            map,
+            options: options.clone(),
+            tiles: ScaledTiles::new(map, options.tile_pixels()),
+        }
+    }
+
+    /// Size of a region's image, or `None` if it doesn't fit in 32 bits
+    pub fn region_size(&self, region: CellRect) -> Option<(u32, u32)> {
+        let tile = self.options.tile_pixels();
+        Some((
+            region.width.checked_mul(tile)?,
+            region.height.checked_mul(tile)?,
+        ))
+    }
+
+    /// Renders a region of the map into an image of its own
+    pub fn render_region(&mut self, region: CellRect) -> RgbaImage {
+        let (width, height) = self.region_size(region).unwrap_or((0, 0));
+        let mut image = RgbaImage::new(width, height);
+        for row in 0..region.height {
+            self.draw_row(&mut image, region, row);
+        }
+        image
+    }
+
+    /// Draws one row of a region's cells into an image created for that region
+    ///
+    /// Drawing row by row lets long renders report progress.
+    pub fn draw_row(&mut self, image: &mut RgbaImage, region: CellRect, row: u32) {
+        let tile_pixels = self.options.tile_pixels();
+        let y = row * tile_pixels;
+
+        for column in 0..region.width {
+            let Some(cell) = self
+                .map
+                .get_cell((region.x + column) as i32, (region.y + row) as i32)
+            else {
+                continue;
+            };
+            let x = column * tile_pixels;
+
+            match self.tiles.get(cell.tile.tile_mapping_index() as usize) {
+                Some(tile) => imageops::replace(image, tile, x as i64, y as i64),
+                None => {
+                    let [r, g, b] = cell_rgb(&cell);
+                    fill_rect(image, x, y, tile_pixels, Rgba([r, g, b, 255]));
+                }
+            }
+
+            for overlay in Overlay::ALL {
+                if !self.options.overlays.contains(&overlay) {
+                    continue;
+                }
+                if let Some(rgb) = overlay.cell_rgb(&cell) {
+                    blend_rect(image, x, y, tile_pixels, rgb);
+                }
+            }
+
+            if self.options.grid {
+                for offset in 0..tile_pixels {
+                    image.put_pixel(x + offset, y, GRID_COLOR);
+                    image.put_pixel(x, y + offset, GRID_COLOR);
+                }
+            }
+        }
+    }
+}
+
+/// Renders a tile group's mappings as one image, `tile_pixels` per cell
+///
+/// Mappings that can't be drawn, because the index is out of range or the
+/// tileset isn't loaded, are left as a dark placeholder.
+pub fn render_tile_group(map: &Map, group: &TileGroup, tile_pixels: u32) -> RgbaImage {
+    let tile_pixels = tile_pixels.max(1);
+    let mut image = RgbaImage::new(group.width * tile_pixels, group.height * tile_pixels);
+    let mut tiles = ScaledTiles::new(map, tile_pixels);
+    for (i, &mapping) in group.mapping_indices.iter().enumerate() {
+        let (column, row) = (i as u32 % group.width.max(1), i as u32 / group.width.max(1));
+        if row >= group.height {
+            break;
+        }
+        let (x, y) = (column * tile_pixels, row * tile_pixels);
+        match tiles.get(mapping as usize) {
+            Some(tile) => imageops::replace(&mut image, tile, x as i64, y as i64),
+            None => fill_rect(&mut image, x, y, tile_pixels, MISSING_TILE_COLOR),
+        }
+    }
+    image
+}
+
+/// Saves an image, choosing the format from the file extension
+///
+/// JPEG has no alpha channel, so images are converted to RGB for it.
+pub fn save_image(image: &RgbaImage, path: &Path) -> ImageResult<()> {
+    match ImageFormat::from_path(path)? {
+        ImageFormat::Jpeg => DynamicImage::ImageRgba8(image.clone()).to_rgb8().save(path),
+        _ => image.save(path),
+    }
+}
+
+/// Tile graphics resized to the output tile size, cached by tileset slot and index
+struct ScaledTiles<'a> {
+    map: &'a Map,
+    tile_pixels: u32,
+    tiles: HashMap<(u16, u32), Option<RgbaImage>>,
+}
+
+impl<'a> ScaledTiles<'a> {
+    fn new(map: &'a Map, tile_pixels: u32) -> Self {
+        Self {
+            map,
+            tile_pixels,
+            tiles: HashMap::new(),
+        }
+    }
+
+    /// Returns the graphic of an entry in the map's tile mapping table
+    fn get(&mut self, mapping_index: usize) -> Option<&RgbaImage> {
+        let map = self.map;
+        let mapping = map.tile_mappings.get(mapping_index)?;
+        let tileset_name = map.tileset_name(mapping.tileset_index)?;
+        let tile_index = mapping.tile_graphic_index as u32;
+        let tile_pixels = self.tile_pixels;
+        self.tiles
+            .entry((mapping.tileset_index, tile_index))
+            .or_insert_with(|| {
+                let tileset = map.tileset_cache.as_ref()?.get_tileset(tileset_name)?;
+                let tile = tileset.tile_image(tile_index)?.to_rgba8();
+                if tile.width() == tile_pixels && tile.height() == tile_pixels {
+                    Some(tile)
+                } else {
+                    Some(imageops::resize(
+                        &tile,
+                        tile_pixels,
+                        tile_pixels,
+                        FilterType::Triangle,
+                    ))
+                }
+            })
+            .as_ref()
+    }
+}
+
+fn fill_rect(image: &mut RgbaImage, x: u32, y: u32, size: u32, color: Rgba<u8>) {
+    for py in y..y + size {
+        for px in x..x + size {
+            image.put_pixel(px, py, color);
+        }
+    }
+}
+
+fn blend_rect(image: &mut RgbaImage, x: u32, y: u32, size: u32, color: [u8; 3]) {
+    for py in y..y + size {
+        for px in x..x + size {
+            let pixel = image.get_pixel_mut(px, py);
+            let alpha = OVERLAY_ALPHA as u16;
+            for (channel, &tint) in pixel.0.iter_mut().zip(&color) {
+                let base = *channel as u16;
+                let tint = tint as u16;
+                *channel = ((base * (255 - alpha) + tint * alpha) / 255) as u8;
+            }
+        }
+    }
+}
+
+/// Color used for a cell when its tile isn't drawn, tinting lava and microbe cells
+pub fn cell_rgb(cell: &Cell) -> [u8; 3] {
+    if cell.tile.is_lava() {
+        [220, 60, 20]
+    } else if cell.tile.is_microbe() {
+        [60, 180, 60]
+    } else {
+        cell_type_rgb(cell.cell_type())
+    }
+}
+
+/// Color used to represent how fast units move over a cell
+pub fn speed_rgb(speed: CellSpeed) -> [u8; 3] {
+    match speed {
+        CellSpeed::Fast => [60, 200, 60],
+        CellSpeed::Medium => [230, 210, 40],
+        CellSpeed::Slow => [240, 130, 30],
+        CellSpeed::Impassible => [220, 40, 40],
+    }
+}
+
+/// Color used to represent a cell type
+pub fn cell_type_rgb(cell_type: CellType) -> [u8; 3] {
+    match cell_type {
+        CellType::FastPassible1 => [150, 140, 110],
+        CellType::FastPassible2 => [130, 130, 130],
+        CellType::MediumPassible1 => [139, 100, 60],
+        CellType::MediumPassible2 => [110, 80, 70],
+        CellType::SlowPassible1 => [80, 60, 55],
+        CellType::SlowPassible2 => [100, 75, 50],
+        CellType::Impassible1 => [70, 70, 70],
+        CellType::Impassible2 => [45, 45, 45],
+        CellType::NorthCliffs | CellType::CliffsHighSide | CellType::CliffsLowSide => [90, 80, 70],
+        CellType::VentsAndFumaroles => [200, 120, 40],
+        CellType::DozedArea => [175, 165, 135],
+        CellType::Rubble => [105, 95, 85],
+        CellType::NormalWall => [255, 255, 255],
+        CellType::MicrobeWall => [144, 238, 144],
+        CellType::LavaWall => [255, 128, 128],
+        CellType::Tube0
+        | CellType::Tube1
+        | CellType::Tube2
+        | CellType::Tube3
+        | CellType::Tube4
+        | CellType::Tube5 => [0, 0, 255],
+        // Padding values the game never uses
+        _ => [255, 0, 255],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::types::{MapInfo, TileMapping, TileWord, TilesetSource};
+    use crate::map::TilesetCache;
+    use std::sync::Arc;
+
+    /// A 3x2 map with one flag set per cell, and a plain cell at (2, 1)
+    fn flagged_map() -> Map {
+        let mut map = Map::new(MapInfo {
+            width: 3,
+            height: 2,
+            ..Default::default()
+        });
+        for y in 0..2 {
+            for x in 0..3 {
+                *map.tile_mut(x, y).unwrap() = TileWord::new(CellType::FastPassible1.index(), 0);
+            }
+        }
+        map.tile_mut(0, 0).unwrap().set_lava_possible(true);
+        map.tile_mut(1, 0).unwrap().set_microbe(true);
+        map.tile_mut(2, 0).unwrap().set_unit_index(5);
+        // Some formats only record that a unit is there, not which one
+        map.set_cell_details(0, 1, 0, false, true);
+        map.tile_mut(1, 1).unwrap().set_wall_or_building(true);
+        map
+    }
+
+    /// Positions of the cells an overlay tints
+    fn tinted_cells(map: &Map, overlay: Overlay) -> Vec<(i32, i32)> {
+        map.cells()
+            .filter(|cell| overlay.cell_rgb(cell).is_some())
+            .map(|cell| (cell.position.x, cell.position.y))
+            .collect()
+    }
+
+    #[test]
+    fn flag_overlays_tint_only_flagged_cells() {
+        let map = flagged_map();
+        assert_eq!(tinted_cells(&map, Overlay::LavaPossible), [(0, 0)]);
+        assert_eq!(tinted_cells(&map, Overlay::Microbe), [(1, 0)]);
+        assert_eq!(tinted_cells(&map, Overlay::Units), [(2, 0), (0, 1)]);
+        assert_eq!(tinted_cells(&map, Overlay::WallOrBuilding), [(1, 1)]);
+        assert!(tinted_cells(&map, Overlay::Expand).is_empty());
+
+        // The cell type overlays color every cell
+        assert_eq!(tinted_cells(&map, Overlay::CellType).len(), 6);
+        assert_eq!(tinted_cells(&map, Overlay::Passability).len(), 6);
+    }
+
+    #[test]
+    fn rendered_overlays_blend_only_flagged_cells() {
+        let map = flagged_map();
+        let mut options = RenderOptions {
+            scale: 1.0 / 32.0,
+            ..Default::default()
+        };
+        let plain = render_map(&map, &options);
+        options.overlays = vec![Overlay::Units];
+        let tinted = render_map(&map, &options);
+
+        for cell in map.cells() {
+            let (x, y) = (cell.position.x as u32, cell.position.y as u32);
+            let flagged = Overlay::Units.cell_rgb(&cell).is_some();
+            assert_eq!(
+                tinted.get_pixel(x, y) != plain.get_pixel(x, y),
+                flagged,
+                "cell ({}, {})",
+                x,
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn overlay_keys_parse_back() {
+        for overlay in Overlay::ALL {
+            assert_eq!(overlay.key().parse::<Overlay>(), Ok(overlay));
+            assert_eq!(
+                overlay.key().to_ascii_uppercase().parse::<Overlay>(),
+                Ok(overlay)
+            );
+        }
+        assert_eq!("cell-type".parse::<Overlay>(), Ok(Overlay::CellType));
+        assert!("lava".parse::<Overlay>().is_err());
+    }
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+    /// A map with one loaded tileset of a red and a blue tile, and a mapping
+    /// for each
+    fn map_with_tiles() -> Map {
+        let mut map = Map::new(MapInfo {
+            width: 1,
+            height: 1,
+            ..Default::default()
+        });
+        map.tileset_sources = vec![TilesetSource {
+            filename: "well0001".into(),
+            num_tiles: 2,
+        }];
+        map.tile_mappings = (0..2)
+            .map(|tile_graphic_index| TileMapping {
+                tile_graphic_index,
+                ..Default::default()
+            })
+            .collect();
+
+        let tileset = RgbaImage::from_fn(32, 64, |_, y| if y < 32 { RED } else { BLUE });
+        let mut cache = TilesetCache::new();
+        cache.add_tileset("well0001".into(), DynamicImage::ImageRgba8(tileset));
+        map.set_tileset_cache(Arc::new(cache));
+        map
+    }
+
+    #[test]
+    fn renders_tile_groups_row_by_row() {
+        let map = map_with_tiles();
+        let group = TileGroup {
+            width: 3,
+            height: 2,
+            // Mapping 9 doesn't exist, and the last cell has no index
+            mapping_indices: vec![0, 1, 9, 1, 0],
+            name: b"test".to_vec(),
+        };
+        let image = render_tile_group(&map, &group, 4);
+        assert_eq!(image.dimensions(), (12, 8));
+
+        let expected = [
+            [RED, BLUE, MISSING_TILE_COLOR],
+            [BLUE, RED, Rgba([0, 0, 0, 0])],
+        ];
+        for (row, colors) in expected.iter().enumerate() {
+            for (column, &color) in colors.iter().enumerate() {
+                let (x, y) = (column as u32 * 4, row as u32 * 4);
+                for (px, py) in [(x, y), (x + 3, y + 3)] {
+                    assert_eq!(
+                        *image.get_pixel(px, py),
+                        color,
+                        "cell ({}, {})",
+                        column,
+                        row
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn renders_tile_groups_without_tilesets_as_placeholders() {
+        let mut map = map_with_tiles();
+        map.tileset_sources[0].filename.clear();
+        let group = TileGroup {
+            width: 2,
+            height: 1,
+            mapping_indices: vec![0, 1],
+            name: Vec::new(),
+        };
+        // A tile size of zero is drawn at one pixel per cell
+        let image = render_tile_group(&map, &group, 0);
+        assert_eq!(image.dimensions(), (2, 1));
+        assert!(image.pixels().all(|&pixel| pixel == MISSING_TILE_COLOR));
+    }
+}
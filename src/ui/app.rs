@@ -5,8 +5,18 @@ use rfd::FileDialog;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use super::{cell_info::CellInfoPanel, map_view::MapView};
-use crate::map::{load_map, load_tilesets, Map, MapInfo, MapLoadError, TilesetCache};
+use super::cell_info::CellInfoPanel;
+use super::image_export::ImageExportDialog;
+use super::map_view::{MapView, MAX_ANIMATION_SPEED, MAX_ZOOM, MIN_ANIMATION_SPEED, MIN_ZOOM};
+use super::minimap::Minimap;
+use super::overlays;
+use super::tile_groups::TileGroupBrowser;
+use super::tileset_browser::TilesetBrowser;
+use crate::map::vol::{self, VolArchive, VolCompression};
+use crate::map::{
+    load_map, load_tilesets, save_map, Diagnostics, Map, MapLoadError, Position, Severity,
+    TilesetCache,
+};
 
 /// Main application state
 pub struct MapViewerApp {
@@ -15,12 +25,20 @@ pub struct MapViewerApp {
     map_path: Option<PathBuf>,
     error_message: Option<String>,
     map_view: MapView,
+    minimap: Minimap,
+    show_minimap: bool,
     cell_info: CellInfoPanel,
     settings_open: bool,
     about_open: bool,
-    selected_cell_pos: Option<(i32, i32)>,
     tileset_cache: Option<Arc<TilesetCache>>,
     tileset_path: Option<PathBuf>,
+    vol_browser: Option<VolArchive>,
+    load_report: Diagnostics,
+    load_report_open: bool,
+    load_report_show_info: bool,
+    image_export: ImageExportDialog,
+    tile_groups: TileGroupBrowser,
+    tileset_browser: TilesetBrowser,
 }
 
 impl Default for MapViewerApp {
@@ -31,12 +49,20 @@ impl Default for MapViewerApp {
             map_path: None,
             error_message: None,
             map_view: MapView::new(),
+            minimap: Minimap::new(),
+            show_minimap: true,
             cell_info: CellInfoPanel::new(),
             settings_open: false,
             about_open: false,
-            selected_cell_pos: None,
             tileset_cache: None,
             tileset_path: None,
+            vol_browser: None,
+            load_report: Diagnostics::new(),
+            load_report_open: false,
+            load_report_show_info: false,
+            image_export: ImageExportDialog::new(),
+            tile_groups: TileGroupBrowser::new(),
+            tileset_browser: TilesetBrowser::new(),
         }
     }
 }
@@ -53,13 +79,15 @@ impl MapViewerApp {
         let mut app = Self::default();
 
         // Try to load tilesets if they're in the expected location
-        let potential_tileset_paths = ["../op2graphics_rs/tilesets.zip", "tilesets.zip"];
+        let potential_tileset_paths = ["../op2graphics_rs/tilesets.zip", "tilesets.zip", "art.vol"];
 
         for path in potential_tileset_paths {
             if Path::new(path).exists() {
-                if let Ok(cache) = load_tilesets(Path::new(path)) {
+                let mut diagnostics = Diagnostics::new();
+                if let Ok(cache) = load_tilesets(Path::new(path), &mut diagnostics) {
                     app.tileset_cache = Some(cache);
                     app.tileset_path = Some(PathBuf::from(path));
+                    app.load_report = diagnostics;
                     break;
                 }
             }
@@ -68,9 +96,21 @@ impl MapViewerApp {
         app
     }
 
+    /// Opens a map, or the map picker for a VOL archive
+    pub fn open_path(&mut self, path: PathBuf) {
+        if vol::is_vol_file(&path) {
+            self.open_vol_browser(&path);
+        } else {
+            self.load_map_file(path);
+        }
+    }
+
     /// Attempts to load a map file
     fn load_map_file(&mut self, path: PathBuf) {
-        match load_map(&path) {
+        let mut diagnostics = Diagnostics::new();
+        let result = load_map(&path, &mut diagnostics);
+        self.set_load_report(diagnostics);
+        match result {
             Ok(mut map) => {
                 // If we have a tileset cache, attach it to the map
                 if let Some(cache) = &self.tileset_cache {
@@ -81,6 +121,13 @@ impl MapViewerApp {
                 self.map_path = Some(path);
                 self.error_message = None;
                 self.map_texture = None; // Will be recreated on next frame
+                self.map_view.fit_map();
+                self.map_view.clear_selection();
+                self.map_view.invalidate_lighting();
+                self.map_view.invalidate_tile_textures();
+                self.minimap.invalidate();
+                self.tile_groups.reset(&mut self.map_view);
+                self.tileset_browser.map_changed();
             }
             Err(MapLoadError::IoError(e)) => {
                 self.error_message = Some(format!("Failed to read map file: {}", e));
@@ -100,6 +147,171 @@ impl MapViewerApp {
         }
     }
 
+    /// Replaces the load report, opening it when something went wrong
+    fn set_load_report(&mut self, diagnostics: Diagnostics) {
+        if diagnostics.max_severity() >= Some(Severity::Warning) {
+            self.load_report_open = true;
+        }
+        self.load_report = diagnostics;
+    }
+
+    /// Shows the diagnostics from the last map or tileset load
+    fn show_load_report(&mut self, ctx: &egui::Context) {
+        let report = &self.load_report;
+        let show_info = &mut self.load_report_show_info;
+        egui::Window::new("Load Report")
+            .open(&mut self.load_report_open)
+            .default_size([520.0, 300.0])
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(format!(
+                        "{} errors, {} warnings",
+                        report.count(Severity::Error),
+                        report.count(Severity::Warning)
+                    ));
+                    ui.checkbox(show_info, "Show info");
+                });
+                ui.separator();
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    egui::Grid::new("load_report_grid")
+                        .striped(true)
+                        .show(ui, |ui| {
+                            let entries = report
+                                .entries()
+                                .iter()
+                                .filter(|entry| *show_info || entry.severity != Severity::Info);
+                            for entry in entries {
+                                let color = match entry.severity {
+                                    Severity::Info => egui::Color32::GRAY,
+                                    Severity::Warning => egui::Color32::YELLOW,
+                                    Severity::Error => egui::Color32::RED,
+                                };
+                                ui.colored_label(color, entry.severity.to_string());
+                                ui.label(&entry.section);
+                                match entry.offset {
+                                    Some(offset) => {
+                                        ui.monospace(format!("{:#x}", offset));
+                                    }
+                                    None => {
+                                        ui.label("");
+                                    }
+                                }
+                                ui.label(&entry.message);
+                                ui.end_row();
+                            }
+                        });
+                });
+            });
+    }
+
+    /// Opens a VOL archive so the user can pick a map from it
+    fn open_vol_browser(&mut self, path: &Path) {
+        match VolArchive::open(path) {
+            Ok(archive) => {
+                self.vol_browser = Some(archive);
+                self.error_message = None;
+            }
+            Err(e) => {
+                self.error_message = Some(format!("Failed to open archive: {}", e));
+            }
+        }
+    }
+
+    /// Shows the list of maps inside the open VOL archive
+    fn show_vol_browser(&mut self, ctx: &egui::Context) {
+        let Some(archive) = &self.vol_browser else {
+            return;
+        };
+
+        let mut open = true;
+        let mut chosen = None;
+        egui::Window::new(format!("Open Map from {}", archive.path().display()))
+            .open(&mut open)
+            .default_size([320.0, 400.0])
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    let maps = archive
+                        .entries()
+                        .iter()
+                        .filter(|entry| entry.name.to_ascii_lowercase().ends_with(".map"));
+                    for entry in maps {
+                        ui.horizontal(|ui| {
+                            if ui.button(&entry.name).clicked() {
+                                chosen = Some(vol::join_vol_path(archive.path(), &entry.name));
+                            }
+                            ui.label(format!("{} bytes", entry.size));
+                            if entry.compression != VolCompression::None {
+                                ui.label(format!("({:?})", entry.compression));
+                            }
+                        });
+                    }
+                });
+            });
+
+        if let Some(path) = chosen {
+            self.load_map_file(path);
+            open = false;
+        }
+        if !open {
+            self.vol_browser = None;
+        }
+    }
+
+    /// Loads tilesets from a zip file, VOL archive or folder and attaches them to the current map
+    fn load_tileset_path(&mut self, path: PathBuf) {
+        let mut diagnostics = Diagnostics::new();
+        let result = load_tilesets(&path, &mut diagnostics);
+        self.set_load_report(diagnostics);
+        match result {
+            Ok(cache) => {
+                self.tileset_cache = Some(cache.clone());
+                self.tileset_path = Some(path);
+
+                // Update the map with the new tileset cache if it exists
+                if let Some(map) = &mut self.map {
+                    map.set_tileset_cache(cache);
+                }
+                self.map_view.invalidate_tile_textures();
+                self.minimap.invalidate();
+                self.tile_groups.reset(&mut self.map_view);
+                self.tileset_browser.reset();
+
+                self.error_message = None;
+            }
+            Err(e) => {
+                self.error_message = Some(format!("Failed to load tilesets: {}", e));
+            }
+        }
+    }
+
+    /// Saves the current map in the native map format
+    fn save_map_file(&mut self, path: PathBuf) {
+        let Some(map) = &self.map else {
+            return;
+        };
+        match save_map(map, &path) {
+            Ok(()) => {
+                self.map_path = Some(path);
+                self.error_message = None;
+            }
+            Err(e) => {
+                self.error_message = Some(format!("Failed to save map: {}", e));
+            }
+        }
+    }
+
+    /// Writes the current map in the format chosen by the file's extension,
+    /// leaving the map's own path unchanged
+    fn export_map_file(&mut self, path: PathBuf) {
+        let Some(map) = &self.map else {
+            return;
+        };
+        if let Err(e) = save_map(map, &path) {
+            self.error_message = Some(format!("Failed to export map: {}", e));
+        }
+    }
+
     /// Shows the main menu bar
     fn show_menu_bar(&mut self, ui: &mut egui::Ui) {
         egui::menu::bar(ui, |ui| {
@@ -107,35 +319,63 @@ impl MapViewerApp {
                 if ui.button("Open Map...").clicked() {
                     if let Some(path) = FileDialog::new()
                         .add_filter("Map Files", &["map"])
+                        .add_filter("Saved Games", &["op2"])
+                        .add_filter("VOL Archives", &["vol"])
+                        .add_filter("JSON Exports", &["json"])
                         .add_filter("Image", &["png", "jpg", "jpeg", "bmp"])
                         .pick_file()
                     {
-                        self.load_map_file(path);
+                        self.open_path(path);
                         ui.close_menu();
                     }
                 }
+                // Saved games keep units and players the map format has no room for
+                let can_save = self.map.as_ref().is_some_and(|map| !map.info.is_saved_game);
+                if ui
+                    .add_enabled(can_save, egui::Button::new("Save Map As..."))
+                    .on_disabled_hover_text("Saved games can only be exported as JSON")
+                    .clicked()
+                {
+                    if let Some(path) = FileDialog::new()
+                        .add_filter("Map Files", &["map"])
+                        .save_file()
+                    {
+                        self.save_map_file(path);
+                        ui.close_menu();
+                    }
+                }
+                if ui
+                    .add_enabled(self.map.is_some(), egui::Button::new("Export JSON..."))
+                    .clicked()
+                {
+                    if let Some(path) = FileDialog::new()
+                        .add_filter("JSON Files", &["json"])
+                        .save_file()
+                    {
+                        self.export_map_file(path.with_extension("json"));
+                        ui.close_menu();
+                    }
+                }
+                if ui
+                    .add_enabled(self.map.is_some(), egui::Button::new("Export Image..."))
+                    .clicked()
+                {
+                    self.image_export.open = true;
+                    ui.close_menu();
+                }
                 if ui.button("Load Tilesets...").clicked() {
                     if let Some(path) = FileDialog::new()
                         .add_filter("Zip Files", &["zip"])
+                        .add_filter("VOL Archives", &["vol"])
                         .pick_file()
                     {
-                        match load_tilesets(&path) {
-                            Ok(cache) => {
-                                self.tileset_cache = Some(cache.clone());
-                                self.tileset_path = Some(path);
-
-                                // Update the map with the new tileset cache if it exists
-                                if let Some(map) = &mut self.map {
-                                    map.set_tileset_cache(cache);
-                                }
-
-                                self.error_message = None;
-                            }
-                            Err(e) => {
-                                self.error_message =
-                                    Some(format!("Failed to load tilesets: {}", e));
-                            }
-                        }
+                        self.load_tileset_path(path);
+                        ui.close_menu();
+                    }
+                }
+                if ui.button("Load Tileset Folder...").clicked() {
+                    if let Some(path) = FileDialog::new().pick_folder() {
+                        self.load_tileset_path(path);
                         ui.close_menu();
                     }
                 }
@@ -150,10 +390,47 @@ impl MapViewerApp {
             });
 
             ui.menu_button("View", |ui| {
+                if ui.button("Fit Map to Window").clicked() {
+                    self.map_view.fit_map();
+                    ui.close_menu();
+                }
+                if ui.button("Zoom to 100%").clicked() {
+                    self.map_view.zoom_to_actual_size();
+                    ui.close_menu();
+                }
+                ui.menu_button("Animation", |ui| {
+                    self.show_animation_controls(ui);
+                });
+                ui.menu_button("Day/Night", |ui| {
+                    self.show_lighting_controls(ui);
+                });
                 let config = self.map_view.config_mut();
-                ui.add(egui::Slider::new(&mut config.zoom_level, 0.1..=5.0).text("Zoom"));
+                ui.add(
+                    egui::Slider::new(&mut config.zoom_level, MIN_ZOOM..=MAX_ZOOM)
+                        .logarithmic(true)
+                        .text("Zoom"),
+                );
                 ui.checkbox(&mut config.show_grid, "Show Grid");
                 ui.checkbox(&mut config.use_tilesets, "Use Tilesets");
+                ui.checkbox(&mut config.show_units, "Show Units");
+                ui.checkbox(&mut self.show_minimap, "Show Minimap");
+                ui.menu_button("Overlays", |ui| {
+                    overlays::overlay_checkboxes(ui, &mut config.overlays);
+                });
+                if ui.button("Tile Groups...").clicked() {
+                    self.tile_groups.open = true;
+                    ui.close_menu();
+                }
+                if ui.button("Tilesets...").clicked() {
+                    self.tileset_browser.open = true;
+                    ui.close_menu();
+                }
+
+                ui.separator();
+                if ui.button("Load Report").clicked() {
+                    self.load_report_open = true;
+                    ui.close_menu();
+                }
 
                 ui.separator();
                 let mut grid_rgb = [
@@ -189,6 +466,54 @@ impl MapViewerApp {
         });
     }
 
+    /// Shows the tile animation toggle, speed, pause and step controls
+    fn show_animation_controls(&mut self, ui: &mut egui::Ui) {
+        let config = self.map_view.config_mut();
+        ui.checkbox(&mut config.animate_tiles, "Animate Tiles");
+        let animating = config.animate_tiles;
+        ui.add_enabled(
+            animating,
+            egui::Slider::new(
+                &mut config.animation_speed,
+                MIN_ANIMATION_SPEED..=MAX_ANIMATION_SPEED,
+            )
+            .logarithmic(true)
+            .text("Speed"),
+        );
+
+        ui.add_enabled_ui(animating, |ui| {
+            ui.horizontal(|ui| {
+                let paused = self.map_view.animation_paused();
+                if ui.button(if paused { "Resume" } else { "Pause" }).clicked() {
+                    self.map_view.set_animation_paused(!paused);
+                }
+                let can_step = paused && self.map.is_some();
+                if ui
+                    .add_enabled(can_step, egui::Button::new("Step"))
+                    .clicked()
+                {
+                    if let Some(map) = &self.map {
+                        self.map_view.step_animation(map);
+                    }
+                }
+            });
+        });
+    }
+
+    /// Shows the day/night preview toggle, time of day and structure light controls
+    fn show_lighting_controls(&mut self, ui: &mut egui::Ui) {
+        let config = self.map_view.config_mut();
+        ui.checkbox(&mut config.show_lighting, "Preview Lighting");
+        ui.add_enabled_ui(config.show_lighting, |ui| {
+            ui.add(
+                egui::Slider::new(&mut config.daylight_position, 0.0..=1.0)
+                    .text("Daylight position"),
+            );
+            ui.checkbox(&mut config.animate_daylight, "Animate");
+            ui.checkbox(&mut config.structure_lights, "Light Around Structures");
+        });
+    }
+
     /// Shows the settings window
     fn show_settings(&mut self, ctx: &egui::Context) {
         egui::Window::new("Settings")
@@ -200,6 +525,7 @@ impl MapViewerApp {
                 ui.add(egui::Slider::new(&mut config.cell_size, 16.0..=64.0).text("Cell Size"));
                 ui.checkbox(&mut config.show_grid, "Show Grid");
                 ui.checkbox(&mut config.use_tilesets, "Use Tilesets");
+                ui.checkbox(&mut config.show_units, "Show Units");
 
                 if let Some(path) = &self.tileset_path {
                     ui.label(format!("Tileset: {}", path.display()));
@@ -251,6 +577,17 @@ impl eframe::App for MapViewerApp {
             self.show_settings(ctx);
         }
 
+        self.show_vol_browser(ctx);
+        self.show_load_report(ctx);
+        self.image_export.show(ctx, self.map.as_ref());
+        self.tile_groups
+            .show(ctx, self.map.as_ref(), &mut self.map_view);
+        self.tileset_browser
+            .show(ctx, self.tileset_cache.as_deref(), self.map.as_ref());
+        if self.map.is_some() {
+            overlays::show_legend(ctx, &self.map_view.config().overlays);
+        }
+
         if self.about_open {
             egui::Window::new("About OP2MapViewer")
                 .collapsible(false)
@@ -277,15 +614,27 @@ impl eframe::App for MapViewerApp {
                         ui.label(&map.info.description);
                         ui.separator();
                     }
-
-                    // Show cell info based on selected position
-                    if let Some((x, y)) = self.selected_cell_pos {
-                        if let Some(cell) = map.get_cell(x, y) {
-                            self.cell_info.show(ui, Some(cell));
-                        }
-                    } else {
-                        self.cell_info.show(ui, None);
+                    if let Some(format) = map.info.source_format {
+                        ui.label(format!("Loaded as {} format", format));
                     }
+                    if map.info.is_saved_game {
+                        ui.label(format!("Saved game, {} units", map.units.len()));
+                        ui.separator();
+                    }
+                    if self.show_minimap {
+                        self.minimap.show(ui, map, &mut self.map_view);
+                        ui.separator();
+                    }
+
+                    // Show the clicked cell and the one under the mouse
+                    let cell_at = |position: Option<Position>| {
+                        position.and_then(|position| map.get_cell(position.x, position.y))
+                    };
+                    let selected = cell_at(self.map_view.selected_cell());
+                    let hovered = cell_at(self.map_view.hovered_cell());
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        self.cell_info.show(ui, map, selected, hovered);
+                    });
                 } else {
                     ui.heading("No Map Loaded");
                     ui.label("Open a map file to begin");
@@ -298,9 +647,7 @@ impl eframe::App for MapViewerApp {
             }
 
             if let Some(map) = &self.map {
-                if let Some(pos) = self.map_view.show(ui, map) {
-                    self.selected_cell_pos = Some((pos.x, pos.y));
-                }
+                self.map_view.show(ui, map);
             } else {
                 ui.centered_and_justified(|ui| {
                     ui.heading("Welcome to OP2MapViewer");
@@ -1,7 +1,8 @@
 //! Cell information panel for OP2MapViewer
 
-use eframe::egui::{Color32, RichText, Ui};
-use crate::map::types::{Cell, CellType};
+use super::map_view::{cell_type_color, player_color};
+use crate::map::types::{unit_type_name, Cell, Map, GAIA_PLAYER};
+use eframe::egui::{self, Color32, RichText, Ui};
 
 /// Widget for displaying detailed cell information
 pub struct CellInfoPanel {
@@ -23,87 +24,188 @@ impl CellInfoPanel {
         Self::default()
     }
 
-    /// Show the cell information panel
-    pub fn show(&mut self, ui: &mut Ui, cell: Option<&Cell>) {
+    /// Show the cell information panel for the selected and hovered cells
+    pub fn show(&mut self, ui: &mut Ui, map: &Map, selected: Option<Cell>, hovered: Option<Cell>) {
         ui.heading("Cell Information");
 
-        if let Some(cell) = cell {
-            // Position
-            ui.horizontal(|ui| {
-                ui.label("Position:");
-                ui.label(RichText::new(format!("({}, {})", cell.position.x, cell.position.y))
-                    .color(Color32::LIGHT_BLUE));
+        egui::CollapsingHeader::new("Selected cell")
+            .default_open(true)
+            .show(ui, |ui| match selected {
+                Some(cell) => self.show_cell(ui, map, cell),
+                None => {
+                    ui.label(RichText::new("Click a cell to select it").weak());
+                }
             });
 
-            // Cell type with color coding
-            ui.horizontal(|ui| {
-                ui.label("Type:");
-                let (text, color) = match cell.cell_type {
-                    CellType::Normal => (String::from("Normal Ground"), Color32::from_gray(180)),
-                    CellType::Lava(variant) => (
-                        format!("Lava Type {}", variant),
-                        Color32::RED,
-                    ),
-                    CellType::Microbe(stage) => (
-                        format!("Microbe Stage {}", stage),
-                        Color32::GREEN,
-                    ),
-                    CellType::Mine(depleted) => (
-                        String::from(if depleted { "Depleted Mine" } else { "Active Mine" }),
-                        if depleted { Color32::GRAY } else { Color32::YELLOW },
-                    ),
-                    CellType::Dirt(variant) => (
-                        format!("Dirt Type {}", variant),
-                        Color32::from_rgb(139, 69, 19),
-                    ),
-                    CellType::Rock(variant) => (
-                        format!("Rock Type {}", variant),
-                        Color32::GRAY,
-                    ),
-                    CellType::Tube(connections) => (
-                        format!("Tube (0b{:08b})", connections),
-                        Color32::BLUE,
-                    ),
-                    CellType::Wall(variant) => (
-                        format!("Wall Type {}", variant),
-                        Color32::WHITE,
-                    ),
-                };
-                ui.label(RichText::new(text).color(color));
+        egui::CollapsingHeader::new("Hovered cell")
+            .default_open(true)
+            .show(ui, |ui| match hovered {
+                Some(cell) => self.show_cell(ui, map, cell),
+                None => {
+                    ui.label(RichText::new("Hover over a cell to see information").weak());
+                }
             });
 
-            // Height with optional gradient visualization
+        // Settings
+        ui.separator();
+        ui.checkbox(&mut self.show_height_gradient, "Show height gradient");
+        ui.checkbox(&mut self.show_details, "Show additional details");
+    }
+
+    /// Show the details of one cell
+    fn show_cell(&self, ui: &mut Ui, map: &Map, cell: Cell) {
+        // Position
+        ui.horizontal(|ui| {
+            ui.label("Position:");
+            ui.label(
+                RichText::new(format!("({}, {})", cell.position.x, cell.position.y))
+                    .color(Color32::LIGHT_BLUE),
+            );
+        });
+
+        // Cell type with color coding
+        ui.horizontal(|ui| {
+            ui.label("Type:");
+            ui.label(
+                RichText::new(cell.cell_type().name()).color(cell_type_color(cell.cell_type())),
+            );
+        });
+        ui.label(RichText::new(cell.cell_type().description()).weak());
+        if let Some(form2) = cell.form2 {
             ui.horizontal(|ui| {
-                ui.label("Height:");
-                if self.show_height_gradient {
-                    let height_color = Color32::from_gray(
-                        ((cell.height as f32 / 255.0) * 200.0 + 55.0) as u8
-                    );
-                    ui.label(RichText::new(format!("{}", cell.height))
-                        .color(height_color));
-                } else {
-                    ui.label(format!("{}", cell.height));
-                }
+                ui.label("FORM2 cell:");
+                ui.label(format!("{}, variant {}", form2.kind.name(), form2.variant));
             });
+        }
+        ui.horizontal(|ui| {
+            ui.label("Speed:");
+            ui.label(format!("{}", cell.cell_type().speed()));
+        });
 
-            // Additional details
-            if self.show_details {
-                if cell.has_wreckage {
-                    ui.label(RichText::new("Contains wreckage")
-                        .color(Color32::DARK_RED));
+        // Height with optional gradient visualization
+        ui.horizontal(|ui| {
+            ui.label("Height:");
+            if self.show_height_gradient {
+                let height_color =
+                    Color32::from_gray(((cell.height as f32 / 255.0) * 200.0 + 55.0) as u8);
+                ui.label(RichText::new(format!("{}", cell.height)).color(height_color));
+            } else {
+                ui.label(format!("{}", cell.height));
+            }
+        });
+
+        // Raw tile word fields
+        let tile = cell.tile;
+        ui.horizontal(|ui| {
+            ui.label("Tile word:");
+            ui.label(RichText::new(format!("{:#010x}", tile.raw())).monospace());
+        });
+        ui.horizontal(|ui| {
+            ui.label("Cell type index:");
+            ui.label(format!("{}", tile.cell_type_index()));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Tile mapping:");
+            ui.label(format!("{}", tile.tile_mapping_index()));
+        });
+        match map.tile_info(tile) {
+            Some(info) => {
+                ui.horizontal(|ui| {
+                    ui.label("Tileset:");
+                    ui.label(format!(
+                        "{} (Index: {})",
+                        info.tileset_name, info.tile_index
+                    ));
+                });
+                if info.mapping.animation_count > 0 {
+                    ui.horizontal(|ui| {
+                        ui.label("Animation:");
+                        ui.label(format!(
+                            "{} extra frames, delay {}",
+                            info.mapping.animation_count, info.mapping.animation_delay
+                        ));
+                    });
                 }
-                if cell.has_unit {
-                    ui.label(RichText::new("Contains unit")
-                        .color(Color32::LIGHT_GREEN));
+            }
+            None => {
+                ui.label(RichText::new("No tile mapping").color(Color32::DARK_RED));
+            }
+        }
+        ui.horizontal(|ui| {
+            ui.label("Unit index:");
+            ui.label(format!("{}", tile.unit_index()));
+        });
+        if let Some(unit) = map.unit(tile.unit_index()) {
+            let (width, height) = unit.footprint();
+            ui.horizontal(|ui| {
+                ui.label("Unit footprint:");
+                ui.label(format!(
+                    "{}x{} at ({}, {})",
+                    width, height, unit.min.x, unit.min.y
+                ));
+            });
+            if let Some(unit_type) = unit.unit_type().and_then(unit_type_name) {
+                ui.horizontal(|ui| {
+                    ui.label("Unit type:");
+                    ui.label(unit_type);
+                });
+            }
+            if let Some(owner) = unit.owner() {
+                let name = if owner == GAIA_PLAYER {
+                    "Gaia".to_string()
+                } else {
+                    format!("Player {}", owner)
+                };
+                ui.horizontal(|ui| {
+                    ui.label("Owner:");
+                    ui.label(RichText::new(name).color(player_color(Some(owner))));
+                });
+            }
+            if let Some(record) = &unit.record {
+                ui.horizontal(|ui| {
+                    ui.label("Unit record:");
+                    ui.label(format!("{} bytes", record.len()));
+                });
+            }
+        }
+
+        let flags = [
+            ("Lava", tile.is_lava(), Color32::RED),
+            (
+                "Lava possible",
+                tile.is_lava_possible(),
+                Color32::from_rgb(255, 140, 0),
+            ),
+            ("Expand", tile.is_expand(), Color32::LIGHT_YELLOW),
+            ("Microbe", tile.is_microbe(), Color32::GREEN),
+            (
+                "Wall or building",
+                tile.is_wall_or_building(),
+                Color32::WHITE,
+            ),
+        ];
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Flags:");
+            let mut any = false;
+            for (name, set, color) in flags {
+                if set {
+                    ui.label(RichText::new(name).color(color));
+                    any = true;
                 }
             }
+            if !any {
+                ui.label(RichText::new("none").weak());
+            }
+        });
 
-            // Settings
-            ui.separator();
-            ui.checkbox(&mut self.show_height_gradient, "Show height gradient");
-            ui.checkbox(&mut self.show_details, "Show additional details");
-        } else {
-            ui.label("Hover over a cell to see information");
+        // Additional details
+        if self.show_details {
+            if cell.has_wreckage {
+                ui.label(RichText::new("Contains wreckage").color(Color32::DARK_RED));
+            }
+            if cell.has_unit {
+                ui.label(RichText::new("Contains unit").color(Color32::LIGHT_GREEN));
+            }
         }
     }
 }
@@ -0,0 +1,290 @@
+//! Export of whole-map images from the viewer
+//!
+//! The map is rendered on the CPU in a background thread, so large exports
+//! don't freeze the window. Rows of cells are drawn straight into the output
+//! image; only images wider or taller than [`MAX_IMAGE_PIXELS`] are split into
+//! numbered files.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use eframe::egui;
+use rfd::FileDialog;
+
+use super::overlays::overlay_checkboxes;
+use crate::map::types::Map;
+use crate::render::{save_image, CellRect, MapRenderer, RenderOptions};
+
+/// Largest width or height of a single exported file, keeping its RGBA
+/// buffer within 1 GiB
+pub const MAX_IMAGE_PIXELS: u32 = 16384;
+
+/// An export running in the background
+struct ExportJob {
+    /// Rows of cells rendered so far; rows split across files count once per file
+    rows_done: Arc<AtomicU32>,
+    total_rows: u32,
+    cancel: Arc<AtomicBool>,
+    result: mpsc::Receiver<Result<Vec<PathBuf>, String>>,
+}
+
+/// Window for exporting the whole map as PNG or JPEG images
+#[derive(Default)]
+pub struct ImageExportDialog {
+    pub open: bool,
+    options: RenderOptions,
+    job: Option<ExportJob>,
+    status: Option<String>,
+}
+
+impl ImageExportDialog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shows the export window if it is open
+    pub fn show(&mut self, ctx: &egui::Context, map: Option<&Map>) {
+        self.poll_job();
+
+        let mut open = self.open;
+        egui::Window::new("Export Image")
+            .open(&mut open)
+            .resizable(false)
+            .default_width(300.0)
+            .show(ctx, |ui| match map {
+                Some(map) => self.show_contents(ui, map),
+                None => {
+                    ui.label("Open a map to export it");
+                }
+            });
+        self.open = open;
+    }
+
+    fn show_contents(&mut self, ui: &mut egui::Ui, map: &Map) {
+        let running = self.job.is_some();
+
+        ui.add_enabled_ui(!running, |ui| {
+            ui.add(
+                egui::Slider::new(&mut self.options.scale, 0.125..=4.0)
+                    .logarithmic(true)
+                    .text("Scale"),
+            );
+            ui.checkbox(&mut self.options.grid, "Grid");
+            ui.collapsing("Overlays", |ui| {
+                overlay_checkboxes(ui, &mut self.options.overlays);
+            });
+        });
+
+        let tile_pixels = self.options.tile_pixels();
+        let files = file_pieces(map, tile_pixels);
+        match self.options.output_size(map) {
+            Some((width, height)) => {
+                ui.label(format!(
+                    "{} px per cell, {}x{} px",
+                    tile_pixels, width, height
+                ));
+            }
+            None => {
+                ui.colored_label(egui::Color32::RED, "The image is too large");
+                return;
+            }
+        }
+        if files.len() > 1 {
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                format!(
+                    "Larger than {} px, so it will be split into {} files named name_<row>_<column>",
+                    MAX_IMAGE_PIXELS,
+                    files.len()
+                ),
+            );
+        }
+
+        ui.separator();
+        if let Some(job) = &self.job {
+            let done = job.rows_done.load(Ordering::Relaxed);
+            let fraction = done as f32 / job.total_rows.max(1) as f32;
+            ui.add(egui::ProgressBar::new(fraction).show_percentage());
+            if ui.button("Cancel").clicked() {
+                job.cancel.store(true, Ordering::Relaxed);
+            }
+        } else if ui.button("Export...").clicked() {
+            if let Some(path) = FileDialog::new()
+                .add_filter("PNG Image", &["png"])
+                .add_filter("JPEG Image", &["jpg", "jpeg"])
+                .set_file_name(format!("{}.png", map.info.name))
+                .save_file()
+            {
+                self.start(ui.ctx(), map, path);
+            }
+        }
+
+        if let Some(status) = &self.status {
+            ui.label(status);
+        }
+    }
+
+    /// Starts rendering a copy of the map in the background
+    fn start(&mut self, ctx: &egui::Context, map: &Map, mut path: PathBuf) {
+        if path.extension().is_none() {
+            path.set_extension("png");
+        }
+        let map = map.clone();
+        let options = self.options.clone();
+        let rows_done = Arc::new(AtomicU32::new(0));
+        let cancel = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel();
+
+        let job = ExportJob {
+            rows_done: rows_done.clone(),
+            total_rows: file_pieces(&map, options.tile_pixels())
+                .into_iter()
+                .map(|(_, _, file)| file.height)
+                .sum(),
+            cancel: cancel.clone(),
+            result: receiver,
+        };
+
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let on_row = || {
+                rows_done.fetch_add(1, Ordering::Relaxed);
+                ctx.request_repaint();
+                !cancel.load(Ordering::Relaxed)
+            };
+            let result = export_map_image(&map, &options, &path, on_row);
+            // The window may have been closed; nobody is waiting then
+            let _ = sender.send(result);
+            ctx.request_repaint();
+        });
+
+        self.job = Some(job);
+        self.status = None;
+    }
+
+    /// Picks up the result of a finished export
+    fn poll_job(&mut self) {
+        let Some(job) = &self.job else {
+            return;
+        };
+        let result = match job.result.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => Err("The export stopped unexpectedly".into()),
+        };
+        self.status = Some(match result {
+            Ok(paths) if paths.len() == 1 => format!("Saved {}", paths[0].display()),
+            Ok(paths) => format!(
+                "The image was too large for one file and was split into {} files, starting with {}",
+                paths.len(),
+                paths[0].display()
+            ),
+            Err(e) => e,
+        });
+        self.job = None;
+    }
+}
+
+/// Renders the whole map and saves it, splitting it into several files only
+/// if it is larger than [`MAX_IMAGE_PIXELS`]
+///
+/// `on_row` is called after each row of cells and returns false to cancel.
+/// Returns the paths of the files written.
+pub fn export_map_image(
+    map: &Map,
+    options: &RenderOptions,
+    path: &Path,
+    mut on_row: impl FnMut() -> bool,
+) -> Result<Vec<PathBuf>, String> {
+    let mut renderer = MapRenderer::new(map, options);
+    let tile_pixels = options.tile_pixels();
+    let files = file_pieces(map, tile_pixels);
+    let single = files.len() == 1;
+
+    let mut written = Vec::new();
+    for (column, row, file) in files {
+        let (width, height) = renderer
+            .region_size(file)
+            .ok_or_else(|| "The image is too large".to_string())?;
+        let mut image = image::RgbaImage::new(width, height);
+        for cell_row in 0..file.height {
+            renderer.draw_row(&mut image, file, cell_row);
+            if !on_row() {
+                return Err("Export cancelled".into());
+            }
+        }
+
+        let piece_path = if single {
+            path.to_path_buf()
+        } else {
+            piece_path(path, column, row)
+        };
+        save_image(&image, &piece_path)
+            .map_err(|e| format!("Failed to save {}: {}", piece_path.display(), e))?;
+        written.push(piece_path);
+    }
+    Ok(written)
+}
+
+/// The files an export is saved as: the whole map, unless it is larger than
+/// [`MAX_IMAGE_PIXELS`]
+fn file_pieces(map: &Map, tile_pixels: u32) -> Vec<(u32, u32, CellRect)> {
+    CellRect::of_map(map).split(MAX_IMAGE_PIXELS / tile_pixels)
+}
+
+/// Path of one piece of a split image: `name_<row>_<column>.png`
+fn piece_path(path: &Path, column: u32, row: u32) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut name = format!("{}_{}_{}", stem, row, column);
+    if let Some(extension) = path.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{CellType, MapInfo, TileWord};
+    use crate::render::render_map;
+
+    #[test]
+    fn exports_the_same_pixels_as_render_map() {
+        let mut map = Map::new(MapInfo {
+            width: 4,
+            height: 3,
+            ..Default::default()
+        });
+        for (i, cell_type) in CellType::ALL.into_iter().take(12).enumerate() {
+            *map.tile_mut(i as i32 % 4, i as i32 / 4).unwrap() =
+                TileWord::new(cell_type.index(), 0);
+        }
+        let options = RenderOptions {
+            scale: 0.25,
+            grid: true,
+            ..Default::default()
+        };
+        let path =
+            std::env::temp_dir().join(format!("op2mapviewer-{}-export.png", std::process::id()));
+
+        let mut rows = 0;
+        let written = export_map_image(&map, &options, &path, || {
+            rows += 1;
+            true
+        })
+        .unwrap();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0], path);
+        assert_eq!(rows, 3);
+
+        let exported = image::open(&path).unwrap().to_rgba8();
+        assert_eq!(exported, render_map(&map, &options));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,10 +1,66 @@
 //! Map viewing widget for OP2MapViewer
 
 use eframe::egui::{self, Rect, Sense, TextureHandle, TextureId, TextureOptions, Ui, Vec2};
-use egui::{Color32, Image, Pos2, Stroke};
-use image::RgbaImage;
-
-use crate::map::types::{Map, Position, TileInfo};
+use egui::{Align2, Color32, FontId, Pos2, Stroke};
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::lighting;
+use crate::map::types::{CellType, Map, Position, TileMapping, MAX_PLAYERS};
+use crate::map::Tileset;
+use crate::render::{self, Overlay};
+
+/// Unit outline colors by owner, in the game's default player colors, with Gaia last
+const PLAYER_COLORS: [Color32; MAX_PLAYERS as usize] = [
+    Color32::from_rgb(40, 110, 255),
+    Color32::from_rgb(230, 30, 30),
+    Color32::from_rgb(30, 200, 30),
+    Color32::from_rgb(255, 220, 0),
+    Color32::from_rgb(0, 220, 220),
+    Color32::from_rgb(220, 0, 220),
+    Color32::from_rgb(150, 150, 150),
+];
+
+/// Outline color for units whose owner couldn't be decoded
+const UNKNOWN_OWNER_COLOR: Color32 = Color32::WHITE;
+
+/// Outline colors for the selected and hovered cells
+const SELECTION_COLOR: Color32 = Color32::from_rgb(0, 220, 255);
+const HOVER_COLOR: Color32 = Color32::from_rgba_premultiplied(160, 160, 160, 160);
+
+/// Fill and outline of cells using highlighted tile mappings
+const HIGHLIGHT_FILL: Color32 = Color32::from_rgba_premultiplied(120, 0, 120, 120);
+const HIGHLIGHT_STROKE: Color32 = Color32::from_rgb(255, 0, 255);
+
+/// Range of `MapViewConfig::zoom_level`
+pub const MIN_ZOOM: f32 = 0.1;
+pub const MAX_ZOOM: f32 = 5.0;
+
+/// Zoom factor per point of mouse wheel scrolling
+const SCROLL_ZOOM_SPEED: f32 = 0.002;
+
+/// Keyboard panning speed in points per second
+const KEY_PAN_SPEED: f32 = 600.0;
+
+/// How much of the map stays on screen when panning, in points
+const MIN_VISIBLE_MAP: f32 = 64.0;
+
+/// Game ticks per second of tile animation at normal speed
+const ANIMATION_TICKS_PER_SECOND: f64 = 40.0;
+
+/// Range of `MapViewConfig::animation_speed`
+pub const MIN_ANIMATION_SPEED: f32 = 0.1;
+pub const MAX_ANIMATION_SPEED: f32 = 4.0;
+
+/// Seconds the daylight band takes to cross the map when animated
+const DAY_LENGTH_SECONDS: f32 = 60.0;
+
+/// View changes that need the size of the view, applied on the next frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ViewCommand {
+    FitMap,
+    ActualSize,
+}
 
 /// Configuration for the map viewer
 #[derive(Clone, Debug)]
@@ -15,6 +71,21 @@ pub struct MapViewConfig {
     pub grid_color: Color32,
     pub background_color: Color32,
     pub use_tilesets: bool,
+    pub show_units: bool,
+    /// Overlays drawn over the cells, in any order
+    pub overlays: Vec<Overlay>,
+    /// Cycle animated tile mappings (lava, microbe, tubes) through their frames
+    pub animate_tiles: bool,
+    /// Animation speed relative to the game's normal speed
+    pub animation_speed: f32,
+    /// Darken the map outside the daylight band
+    pub show_lighting: bool,
+    /// Centre of the daylight band as a fraction of the map width
+    pub daylight_position: f32,
+    /// Move the daylight band across the map over time
+    pub animate_daylight: bool,
+    /// Light the ground around buildings and units at night
+    pub structure_lights: bool,
 }
 
 impl Default for MapViewConfig {
@@ -26,6 +97,14 @@ impl Default for MapViewConfig {
             grid_color: Color32::from_gray(128),
             background_color: Color32::BLACK,
             use_tilesets: true,
+            show_units: true,
+            overlays: Vec::new(),
+            animate_tiles: false,
+            animation_speed: 1.0,
+            show_lighting: false,
+            daylight_position: 0.5,
+            animate_daylight: false,
+            structure_lights: true,
         }
     }
 }
@@ -34,148 +113,220 @@ impl Default for MapViewConfig {
 pub struct MapView {
     config: MapViewConfig,
     pan_offset: Vec2,
-    dragging: bool,
-    drag_start: Option<Pos2>,
-    drag_start_offset: Option<Vec2>,
     hovered_cell: Option<Position>,
-    tile_textures: std::collections::HashMap<String, TextureHandle>,
+    /// Cell pinned by clicking, shown until another cell is clicked
+    selected_cell: Option<Position>,
+    /// GPU textures of the tilesets drawn so far, keyed by tileset name
+    tile_textures: std::collections::HashMap<String, TilesetTextures>,
+    /// Zoom level at the end of the last frame, to notice changes made elsewhere
+    last_zoom: f32,
+    /// Screen area the map was drawn in during the last frame
+    view_rect: Option<Rect>,
+    pending_command: Option<ViewCommand>,
+    /// Tile mapping indices whose cells are highlighted, e.g. by the tile group browser
+    highlighted_mappings: HashSet<u16>,
+    /// Game ticks of tile animation shown so far
+    animation_tick: f64,
+    /// Input time the animation was last advanced at, while it is running
+    animation_time: Option<f64>,
+    animation_paused: bool,
+    /// Light from structures for each cell, computed when first needed
+    structure_light: Option<Vec<f32>>,
 }
 
-impl MapView {
-    pub fn new() -> Self {
+/// GPU textures for one tileset, split into pages that fit the texture size limit
+pub(super) struct TilesetTextures {
+    pages: Vec<(TextureHandle, Tileset)>,
+    tiles_per_page: u32,
+}
+
+impl TilesetTextures {
+    /// Uploads a tileset, as several pages if it is too large for one texture
+    pub(super) fn load(ctx: &egui::Context, tileset_name: &str, tileset: &Tileset) -> Self {
+        let max_side = ctx.input(|i| i.max_texture_side) as u32;
+        let pages = tileset
+            .split_pages(max_side)
+            .into_iter()
+            .enumerate()
+            .map(|(page_index, page)| {
+                // Convert the image to RGBA8 format
+                let rgba_image = page.image().to_rgba8();
+                let size = [rgba_image.width() as usize, rgba_image.height() as usize];
+
+                // Create a texture from the image
+                let texture = ctx.load_texture(
+                    format!("{}#{}", tileset_name, page_index),
+                    egui::ColorImage::from_rgba_unmultiplied(
+                        size,
+                        rgba_image.as_flat_samples().as_slice(),
+                    ),
+                    TextureOptions::default(),
+                );
+                (texture, page)
+            })
+            .collect();
+
+        Self {
+            pages,
+            tiles_per_page: tileset.tiles_per_page(max_side),
+        }
+    }
+
+    /// Texture and UV rectangle of a single tile
+    pub(super) fn tile(&self, tile_index: u32) -> Option<(TextureId, Rect)> {
+        let (texture, page) = self
+            .pages
+            .get((tile_index / self.tiles_per_page) as usize)?;
+        let (min, max) = page.tile_uv(tile_index % self.tiles_per_page)?;
+        Some((
+            texture.id(),
+            Rect::from_min_max(Pos2::new(min[0], min[1]), Pos2::new(max[0], max[1])),
+        ))
+    }
+}
+
+impl Default for MapView {
+    fn default() -> Self {
         Self {
             config: MapViewConfig::default(),
             pan_offset: Vec2::ZERO,
-            dragging: false,
-            drag_start: None,
-            drag_start_offset: None,
             hovered_cell: None,
+            selected_cell: None,
             tile_textures: std::collections::HashMap::new(),
+            last_zoom: 1.0,
+            view_rect: None,
+            pending_command: None,
+            highlighted_mappings: HashSet::new(),
+            animation_tick: 0.0,
+            animation_time: None,
+            animation_paused: false,
+            structure_light: None,
         }
     }
+}
+
+impl MapView {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
     #[allow(dead_code)]
     pub fn with_config(config: MapViewConfig) -> Self {
         Self {
+            last_zoom: config.zoom_level,
             config,
-            pan_offset: Vec2::ZERO,
-            dragging: false,
-            drag_start: None,
-            drag_start_offset: None,
-            hovered_cell: None,
-            tile_textures: std::collections::HashMap::new(),
+            ..Self::default()
         }
     }
 
-    /// Get or create a texture for a tileset
+    /// Get or create the textures for a tileset
     fn get_or_create_tile_texture(
         &mut self,
         ui: &mut Ui,
         map: &Map,
         tileset_name: &str,
-    ) -> Option<TextureHandle> {
-        if self.tile_textures.contains_key(tileset_name) {
-            return self.tile_textures.get(tileset_name).cloned();
-        }
-
-        // If we have a tileset cache, load the texture
-        if let Some(cache) = &map.tileset_cache {
-            if let Some(image) = cache.get_tileset(tileset_name) {
-                // Convert the image to RGBA8 format
-                let rgba_image = image.to_rgba8();
-                let size = [rgba_image.width() as usize, rgba_image.height() as usize];
-
-                // Create a texture from the image
-                let texture = ui.ctx().load_texture(
-                    tileset_name,
-                    egui::ColorImage::from_rgba_unmultiplied(
-                        size,
-                        rgba_image.as_flat_samples().as_slice(),
-                    ),
-                    TextureOptions::default(),
-                );
-
-                self.tile_textures
-                    .insert(tileset_name.to_string(), texture.clone());
-                return Some(texture);
-            }
+    ) -> Option<&TilesetTextures> {
+        if !self.tile_textures.contains_key(tileset_name) {
+            // If we have a tileset cache, load the texture
+            let tileset = map.tileset_cache.as_ref()?.get_tileset(tileset_name)?;
+            let textures = TilesetTextures::load(ui.ctx(), tileset_name, tileset);
+            self.tile_textures
+                .insert(tileset_name.to_string(), textures);
         }
 
-        None
-    }
-
-    /// Extract a single tile from a tileset texture
-    fn extract_tile(&self, tileset: &TextureHandle, tile_index: u32) -> (TextureId, Rect) {
-        // Assuming tileset is a texture atlas with tiles laid out in a grid
-        // For this simple implementation, assuming 32x32 tiles in a horizontal strip
-        let tile_size = 32.0;
-        let x = (tile_index as f32) * tile_size;
-
-        // The texture UV coordinates are normalized [0.0-1.0]
-        let texture_size = tileset.size_vec2();
-        let uv_min_x = x / texture_size.x;
-        let uv_min_y = 0.0;
-        let uv_max_x = (x + tile_size) / texture_size.x;
-        let uv_max_y = tile_size / texture_size.y;
-
-        (
-            tileset.id(),
-            Rect::from_min_max(Pos2::new(uv_min_x, uv_min_y), Pos2::new(uv_max_x, uv_max_y)),
-        )
+        self.tile_textures.get(tileset_name)
     }
 
     /// Show the map viewer widget
-    pub fn show(&mut self, ui: &mut Ui, map: &Map) -> Option<Position> {
+    ///
+    /// Dragging pans the map and clicking a cell selects it; see
+    /// [`MapView::hovered_cell`] and [`MapView::selected_cell`].
+    pub fn show(&mut self, ui: &mut Ui, map: &Map) {
         let (response, painter) = ui.allocate_painter(ui.available_size(), Sense::click_and_drag());
 
-        // Handle mouse input
-        if response.clicked() {
-            self.dragging = true;
-            if let Some(pos) = response.hover_pos() {
-                self.drag_start = Some(pos);
-                self.drag_start_offset = Some(self.pan_offset);
-            }
-        }
-        if response.drag_stopped() {
-            self.dragging = false;
-            self.drag_start = None;
-            self.drag_start_offset = None;
+        // egui only reports a drag once the pointer has moved, so clicks don't pan
+        if response.dragged() {
+            self.pan_offset += response.drag_delta();
         }
-        if self.dragging {
-            if let (Some(start), Some(start_offset)) = (self.drag_start, self.drag_start_offset) {
-                if let Some(current) = response.hover_pos() {
-                    self.pan_offset = start_offset + (current - start);
-                }
-            }
+
+        let visible_rect = response.rect;
+        self.view_rect = Some(visible_rect);
+        self.handle_zoom(ui, &response, map);
+        self.handle_keyboard(ui, visible_rect);
+        self.clamp_pan(visible_rect, map);
+        self.advance_animation(ui, map);
+        self.advance_daylight(ui);
+        if self.config.show_lighting && self.config.structure_lights {
+            self.structure_light
+                .get_or_insert_with(|| lighting::structure_light(map));
         }
+        let animation_tick = self
+            .config
+            .animate_tiles
+            .then_some(self.animation_tick as u64);
 
         // Calculate visible area
         let cell_size = self.config.cell_size * self.config.zoom_level;
-        // Calculate map dimensions for potential future use in viewport bounds checking
-        let _map_width = (map.info.width as f32) * cell_size;
-        let _map_height = (map.info.height as f32) * cell_size;
 
         // Draw background
-        painter.rect_filled(response.rect, 0.0, self.config.background_color);
+        painter.rect_filled(visible_rect, 0.0, self.config.background_color);
 
-        // Draw cells
-        let visible_rect = response.rect;
+        // Screen position of the map's top-left corner
         let offset = self.pan_offset + visible_rect.center().to_vec2();
 
         // Calculate visible cell range
-        let min_x = (((-offset.x) / cell_size) - 1.0).floor() as i32;
-        let min_y = (((-offset.y) / cell_size) - 1.0).floor() as i32;
-        let max_x = (((visible_rect.width() - offset.x) / cell_size) + 1.0).ceil() as i32;
-        let max_y = (((visible_rect.height() - offset.y) / cell_size) + 1.0).ceil() as i32;
-
-        // Update hovered cell
-        self.hovered_cell = response.hover_pos().map(|pos| {
-            let map_pos = pos - offset;
-            Position::new(
-                (map_pos.x / cell_size).floor() as i32,
-                (map_pos.y / cell_size).floor() as i32,
-            )
-        });
+        let min_x = ((visible_rect.min.x - offset.x) / cell_size).floor() as i32;
+        let min_y = ((visible_rect.min.y - offset.y) / cell_size).floor() as i32;
+        let max_x = ((visible_rect.max.x - offset.x) / cell_size).ceil() as i32;
+        let max_y = ((visible_rect.max.y - offset.y) / cell_size).ceil() as i32;
+
+        // Update hovered cell, ignoring the area around the map
+        self.hovered_cell = response
+            .hover_pos()
+            .map(|pos| {
+                let map_pos = pos - offset;
+                Position::new(
+                    (map_pos.x / cell_size).floor() as i32,
+                    (map_pos.y / cell_size).floor() as i32,
+                )
+            })
+            .filter(|position| map.get_cell(position.x, position.y).is_some());
+
+        // Clicking a cell selects it; clicking outside the map or Escape clears the selection
+        if response.clicked() {
+            self.selected_cell = self.hovered_cell;
+        }
+        if !ui.ctx().wants_keyboard_input() && ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+            self.selected_cell = None;
+        }
+
+        // Upload every tileset the map uses, then index the textures by tileset
+        // slot so cells don't need to look them up by name
+        let use_tilesets = self.config.use_tilesets && map.tileset_cache.is_some();
+        if use_tilesets {
+            for slot in 0..map.tileset_sources.len() as u16 {
+                if let Some(name) = map.tileset_name(slot) {
+                    self.get_or_create_tile_texture(ui, map, name);
+                }
+            }
+        }
+        let slot_textures: Vec<Option<&TilesetTextures>> = (0..map.tileset_sources.len() as u16)
+            .map(|slot| {
+                map.tileset_name(slot)
+                    .and_then(|name| self.tile_textures.get(name))
+            })
+            .collect();
+
+        let structure_light = self
+            .structure_light
+            .as_deref()
+            .filter(|_| self.config.structure_lights);
+
+        // Overlays stack in a fixed order whatever order they were enabled in
+        let overlays: Vec<Overlay> = Overlay::ALL
+            .into_iter()
+            .filter(|overlay| self.config.overlays.contains(overlay))
+            .collect();
 
         // Draw visible cells
         for y in min_y..max_y {
@@ -193,38 +344,75 @@ impl MapView {
                         Vec2::splat(cell_size),
                     );
 
-                    // Check if we should use tilesets and if this cell has tileset info
-                    let use_tile = self.config.use_tilesets
-                        && cell.tile_info.is_some()
-                        && map.tileset_cache.is_some();
-
-                    if use_tile {
-                        if let Some(tile_info) = &cell.tile_info {
-                            if let Some(texture) =
-                                self.get_or_create_tile_texture(ui, map, &tile_info.tileset_name)
-                            {
-                                // Extract the specific tile from the tileset
-                                let (texture_id, uv_rect) =
-                                    self.extract_tile(&texture, tile_info.tile_index);
-
-                                // Draw the tile
-                                painter.image(texture_id, cell_rect, uv_rect, Color32::WHITE);
-                            } else {
-                                // Fallback to colored rectangle if texture loading failed
-                                let cell_color = get_cell_color(cell);
-                                painter.rect_filled(cell_rect, 0.0, cell_color);
-                            }
-                        } else {
-                            // Fallback to colored rectangle if no tile info
-                            let cell_color = get_cell_color(cell);
-                            painter.rect_filled(cell_rect, 0.0, cell_color);
-                        }
+                    // Resolve the cell's graphic through the map's tile mapping table
+                    let tile_info = if use_tilesets {
+                        map.tile_info(cell.tile)
+                    } else {
+                        None
+                    };
+
+                    // Extract the specific tile from the tileset, picking the
+                    // current frame of animated mappings
+                    let tile = tile_info.and_then(|tile_info| {
+                        let tile_index = match animation_tick {
+                            Some(tick) => tile_info.mapping.graphic_at(tick),
+                            None => tile_info.tile_index,
+                        };
+                        slot_textures
+                            .get(tile_info.tileset_index as usize)
+                            .copied()
+                            .flatten()?
+                            .tile(tile_index)
+                    });
+
+                    if let Some((texture_id, uv_rect)) = tile {
+                        // Draw the tile
+                        painter.image(texture_id, cell_rect, uv_rect, Color32::WHITE);
                     } else {
-                        // Use colored rectangle representation
-                        let cell_color = get_cell_color(cell);
+                        // Fallback to colored rectangle if there is no tile or texture
+                        let cell_color = get_cell_color(&cell);
                         painter.rect_filled(cell_rect, 0.0, cell_color);
                     }
 
+                    if self.config.show_lighting {
+                        let daylight = lighting::daylight_brightness(
+                            x as u32,
+                            map.info.width,
+                            self.config.daylight_position,
+                        );
+                        let lit = structure_light
+                            .and_then(|light| light.get((y * map.info.width as i32 + x) as usize))
+                            .copied()
+                            .unwrap_or(0.0);
+                        let brightness = lighting::cell_brightness(daylight, lit);
+                        if brightness < 1.0 {
+                            let darkness = ((1.0 - brightness) * 255.0) as u8;
+                            painter.rect_filled(
+                                cell_rect,
+                                0.0,
+                                Color32::from_black_alpha(darkness),
+                            );
+                        }
+                    }
+
+                    for &overlay in &overlays {
+                        if let Some(color) = overlay_color(overlay, &cell) {
+                            painter.rect_filled(cell_rect, 0.0, color);
+                        }
+                    }
+
+                    if self
+                        .highlighted_mappings
+                        .contains(&cell.tile.tile_mapping_index())
+                    {
+                        painter.rect_filled(cell_rect, 0.0, HIGHLIGHT_FILL);
+                        painter.rect_stroke(
+                            cell_rect.shrink(0.5),
+                            0.0,
+                            Stroke::new(1.0, HIGHLIGHT_STROKE),
+                        );
+                    }
+
                     // Draw grid if enabled
                     if self.config.show_grid {
                         painter.rect_stroke(
@@ -237,12 +425,294 @@ impl MapView {
             }
         }
 
-        // Return hovered cell position if any
+        // Draw unit markers over the terrain
+        if self.config.show_units {
+            for unit in &map.units {
+                if unit.max.x < min_x
+                    || unit.min.x >= max_x
+                    || unit.max.y < min_y
+                    || unit.min.y >= max_y
+                {
+                    continue;
+                }
+                let unit_rect = Rect::from_min_max(
+                    Pos2::new(
+                        offset.x + (unit.min.x as f32 * cell_size),
+                        offset.y + (unit.min.y as f32 * cell_size),
+                    ),
+                    Pos2::new(
+                        offset.x + ((unit.max.x + 1) as f32 * cell_size),
+                        offset.y + ((unit.max.y + 1) as f32 * cell_size),
+                    ),
+                );
+                let color = player_color(unit.owner());
+                painter.rect_stroke(unit_rect.shrink(1.0), 2.0, Stroke::new(2.0, color));
+                // Label units with their index once there's room for it
+                if cell_size >= 16.0 {
+                    painter.text(
+                        unit_rect.center(),
+                        Align2::CENTER_CENTER,
+                        unit.index.to_string(),
+                        FontId::monospace((cell_size * 0.4).min(14.0)),
+                        color,
+                    );
+                }
+            }
+        }
+
+        // Outline the hovered and selected cells
+        let cell_rect = |position: Position| {
+            Rect::from_min_size(
+                Pos2::new(
+                    offset.x + (position.x as f32 * cell_size),
+                    offset.y + (position.y as f32 * cell_size),
+                ),
+                Vec2::splat(cell_size),
+            )
+        };
+        if let Some(hovered) = self
+            .hovered_cell
+            .filter(|&cell| Some(cell) != self.selected_cell)
+        {
+            painter.rect_stroke(cell_rect(hovered), 0.0, Stroke::new(1.0, HOVER_COLOR));
+        }
+        if let Some(selected) = self.selected_cell {
+            painter.rect_stroke(cell_rect(selected), 0.0, Stroke::new(2.0, SELECTION_COLOR));
+        }
+    }
+
+    /// Cell under the mouse pointer during the last frame
+    pub fn hovered_cell(&self) -> Option<Position> {
         self.hovered_cell
     }
 
+    /// Cell picked by clicking, if any
+    pub fn selected_cell(&self) -> Option<Position> {
+        self.selected_cell
+    }
+
+    /// Forgets the selected cell, e.g. when another map is opened
+    pub fn clear_selection(&mut self) {
+        self.selected_cell = None;
+    }
+
+    /// Highlights every cell drawn with one of the given tile mappings
+    pub fn set_highlighted_mappings(&mut self, mappings: impl IntoIterator<Item = u16>) {
+        self.highlighted_mappings = mappings.into_iter().collect();
+    }
+
+    pub fn clear_highlight(&mut self) {
+        self.highlighted_mappings.clear();
+    }
+
+    /// Drops the cached structure lighting, e.g. after opening another map
+    pub fn invalidate_lighting(&mut self) {
+        self.structure_light = None;
+    }
+
+    /// Drops the uploaded tileset textures, e.g. after loading other tilesets
+    pub fn invalidate_tile_textures(&mut self) {
+        self.tile_textures.clear();
+    }
+
+    pub fn animation_paused(&self) -> bool {
+        self.animation_paused
+    }
+
+    pub fn set_animation_paused(&mut self, paused: bool) {
+        self.animation_paused = paused;
+    }
+
+    /// Advances tile animation to the next tick where any mapping changes frame
+    pub fn step_animation(&mut self, map: &Map) {
+        if let Some(next) = self.next_frame_tick(map) {
+            self.animation_tick = next as f64;
+        }
+    }
+
+    /// The next tick where any mapping changes frame, if any are animated
+    fn next_frame_tick(&self, map: &Map) -> Option<u64> {
+        let tick = self.animation_tick as u64;
+        map.tile_mappings
+            .iter()
+            .filter(|mapping| mapping.is_animated())
+            .map(|mapping| (tick / mapping.frame_ticks() + 1) * mapping.frame_ticks())
+            .min()
+    }
+
+    /// The part of the map shown in the last frame, in cells
+    ///
+    /// The rectangle may extend past the map's edges.
+    pub fn visible_cells(&self) -> Option<Rect> {
+        let view_rect = self.view_rect?;
+        let cell_size = self.config.cell_size * self.config.zoom_level;
+        let offset = self.pan_offset + view_rect.center().to_vec2();
+        Some(Rect::from_min_max(
+            ((view_rect.min.to_vec2() - offset) / cell_size).to_pos2(),
+            ((view_rect.max.to_vec2() - offset) / cell_size).to_pos2(),
+        ))
+    }
+
+    /// Pans so the given point, in cells, is in the middle of the view
+    pub fn center_on(&mut self, cell: Pos2) {
+        self.pan_offset = -cell.to_vec2() * self.config.cell_size * self.config.zoom_level;
+    }
+
+    /// Fits the whole map into the view on the next frame
+    pub fn fit_map(&mut self) {
+        self.pending_command = Some(ViewCommand::FitMap);
+    }
+
+    /// Zooms so tiles are drawn at their own size, keeping the view centre in place
+    pub fn zoom_to_actual_size(&mut self) {
+        self.pending_command = Some(ViewCommand::ActualSize);
+    }
+
+    /// Applies zoom commands, the zoom slider, and mouse wheel or pinch zooming
+    fn handle_zoom(&mut self, ui: &Ui, response: &egui::Response, map: &Map) {
+        let rect = response.rect;
+
+        // The zoom slider changes the zoom level directly; keep the view centre still
+        if self.config.zoom_level != self.last_zoom {
+            let zoom = self.config.zoom_level;
+            self.config.zoom_level = self.last_zoom;
+            self.zoom_at(zoom, rect.center(), rect);
+        }
+
+        match self.pending_command.take() {
+            Some(ViewCommand::FitMap) => {
+                let map_size = Vec2::new(map.info.width as f32, map.info.height as f32)
+                    * self.config.cell_size;
+                if map_size.x > 0.0 && map_size.y > 0.0 {
+                    let zoom = (rect.width() / map_size.x).min(rect.height() / map_size.y);
+                    self.config.zoom_level = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+                    self.pan_offset = -map_size * self.config.zoom_level / 2.0;
+                }
+            }
+            Some(ViewCommand::ActualSize) => {
+                let zoom = Tileset::DEFAULT_TILE_SIZE as f32 / self.config.cell_size;
+                self.zoom_at(zoom, rect.center(), rect);
+            }
+            None => {}
+        }
+
+        // Zoom around the cursor so the cell under it stays put
+        if let Some(pointer) = response.hover_pos() {
+            let (scroll, pinch) = ui.input(|i| (i.smooth_scroll_delta.y, i.zoom_delta()));
+            let factor = pinch * (scroll * SCROLL_ZOOM_SPEED).exp();
+            if factor != 1.0 {
+                self.zoom_at(self.config.zoom_level * factor, pointer, rect);
+            }
+        }
+
+        self.last_zoom = self.config.zoom_level;
+    }
+
+    /// Changes the zoom level, keeping the map point under `anchor` in place
+    fn zoom_at(&mut self, zoom: f32, anchor: Pos2, rect: Rect) {
+        let zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        let center = rect.center().to_vec2();
+        let anchor = anchor.to_vec2();
+        let offset = self.pan_offset + center;
+        let new_offset = anchor - (anchor - offset) * (zoom / self.config.zoom_level);
+        self.pan_offset = new_offset - center;
+        self.config.zoom_level = zoom;
+    }
+
+    /// Moves tile animation on by the time since it was last advanced
+    ///
+    /// A repaint is requested for when the next frame of any mapping is due,
+    /// rather than every frame, and only while there is something to animate.
+    fn advance_animation(&mut self, ui: &Ui, map: &Map) {
+        if !self.config.animate_tiles
+            || self.animation_paused
+            || !map.tile_mappings.iter().any(TileMapping::is_animated)
+        {
+            self.animation_time = None;
+            return;
+        }
+        let ticks_per_second = ANIMATION_TICKS_PER_SECOND * self.config.animation_speed as f64;
+        let now = ui.input(|i| i.time);
+        if let Some(last) = self.animation_time {
+            self.animation_tick += (now - last) * ticks_per_second;
+        }
+        self.animation_time = Some(now);
+
+        if let Some(next) = self.next_frame_tick(map) {
+            let seconds = (next as f64 - self.animation_tick) / ticks_per_second;
+            ui.ctx()
+                .request_repaint_after(Duration::from_secs_f64(seconds.max(0.0)));
+        }
+    }
+
+    /// Moves the daylight band from east to west while it is animated
+    fn advance_daylight(&mut self, ui: &Ui) {
+        if !self.config.show_lighting || !self.config.animate_daylight {
+            return;
+        }
+        let dt = ui.input(|i| i.stable_dt);
+        self.config.daylight_position =
+            (self.config.daylight_position - dt / DAY_LENGTH_SECONDS).rem_euclid(1.0);
+        ui.ctx().request_repaint();
+    }
+
+    /// Pans with the arrow keys or WASD, and by a screen with Page Up and Page Down
+    fn handle_keyboard(&mut self, ui: &Ui, rect: Rect) {
+        // Keys belong to text fields while one has focus
+        if ui.ctx().wants_keyboard_input() {
+            return;
+        }
+
+        let (direction, page, dt) = ui.input(|i| {
+            let held = |keys: [egui::Key; 2]| keys.iter().any(|&key| i.key_down(key));
+            let mut direction = Vec2::ZERO;
+            if held([egui::Key::ArrowLeft, egui::Key::A]) {
+                direction.x -= 1.0;
+            }
+            if held([egui::Key::ArrowRight, egui::Key::D]) {
+                direction.x += 1.0;
+            }
+            if held([egui::Key::ArrowUp, egui::Key::W]) {
+                direction.y -= 1.0;
+            }
+            if held([egui::Key::ArrowDown, egui::Key::S]) {
+                direction.y += 1.0;
+            }
+            let mut page = 0.0;
+            if i.key_pressed(egui::Key::PageUp) {
+                page -= 1.0;
+            }
+            if i.key_pressed(egui::Key::PageDown) {
+                page += 1.0;
+            }
+            (direction, page, i.stable_dt)
+        });
+
+        // Moving the view right moves the map left
+        if direction != Vec2::ZERO {
+            self.pan_offset -= direction.normalized() * KEY_PAN_SPEED * dt;
+            ui.ctx().request_repaint();
+        }
+        self.pan_offset.y -= page * rect.height() * 0.9;
+    }
+
+    /// Keeps part of the map on screen so it can't be lost by panning
+    fn clamp_pan(&mut self, rect: Rect, map: &Map) {
+        let cell_size = self.config.cell_size * self.config.zoom_level;
+        let map_size = Vec2::new(map.info.width as f32, map.info.height as f32) * cell_size;
+        let margin = Vec2::new(
+            MIN_VISIBLE_MAP.min(map_size.x),
+            MIN_VISIBLE_MAP.min(map_size.y),
+        );
+        let half_view = rect.size() / 2.0;
+
+        // pan_offset is the map's top-left corner relative to the view centre
+        let min = -half_view - map_size + margin;
+        let max = half_view - margin;
+        self.pan_offset = self.pan_offset.max(min).min(max);
+    }
+
     /// Get the current configuration
-    #[allow(dead_code)]
     pub fn config(&self) -> &MapViewConfig {
         &self.config
     }
@@ -253,22 +723,33 @@ impl MapView {
     }
 }
 
-/// Helper function to get a color for a cell type
+/// Helper function to get a color for a cell, tinting lava and microbe cells
 fn get_cell_color(cell: &crate::map::types::Cell) -> Color32 {
-    match cell.cell_type {
-        crate::map::types::CellType::Normal => Color32::from_gray(64),
-        crate::map::types::CellType::Lava(_) => Color32::RED,
-        crate::map::types::CellType::Microbe(_) => Color32::GREEN,
-        crate::map::types::CellType::Mine(depleted) => {
-            if depleted {
-                Color32::DARK_GRAY
-            } else {
-                Color32::YELLOW
-            }
-        }
-        crate::map::types::CellType::Dirt(_) => Color32::from_rgb(139, 69, 19),
-        crate::map::types::CellType::Rock(_) => Color32::GRAY,
-        crate::map::types::CellType::Tube(_) => Color32::BLUE,
-        crate::map::types::CellType::Wall(_) => Color32::WHITE,
-    }
+    let [r, g, b] = render::cell_rgb(cell);
+    Color32::from_rgb(r, g, b)
+}
+
+/// Semi-transparent tint an overlay draws over a cell, if it marks the cell
+pub fn overlay_color(overlay: Overlay, cell: &crate::map::types::Cell) -> Option<Color32> {
+    let [r, g, b] = overlay.cell_rgb(cell)?;
+    Some(Color32::from_rgba_unmultiplied(
+        r,
+        g,
+        b,
+        render::OVERLAY_ALPHA,
+    ))
+}
+
+/// Color used to represent a cell type when tiles aren't drawn
+pub fn cell_type_color(cell_type: CellType) -> Color32 {
+    let [r, g, b] = render::cell_type_rgb(cell_type);
+    Color32::from_rgb(r, g, b)
+}
+
+/// Outline color of a unit owned by `owner`
+pub fn player_color(owner: Option<u8>) -> Color32 {
+    owner
+        .and_then(|owner| PLAYER_COLORS.get(owner as usize))
+        .copied()
+        .unwrap_or(UNKNOWN_OWNER_COLOR)
 }
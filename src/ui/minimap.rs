@@ -0,0 +1,111 @@
+//! Minimap widget for OP2MapViewer
+//!
+//! Shows the whole map at one pixel per cell with the main view's visible area
+//! outlined. Clicking or dragging on the minimap moves the main view there.
+
+use eframe::egui::{self, Color32, Pos2, Rect, Sense, Stroke, TextureHandle, TextureOptions, Ui};
+
+use super::map_view::MapView;
+use crate::map::types::Map;
+use crate::map::Tileset;
+use crate::render::{render_map, RenderOptions};
+
+/// Largest size of the minimap on screen, in points
+const MAX_SIZE: f32 = 256.0;
+
+/// Outline color of the main view's visible area
+const VIEWPORT_COLOR: Color32 = Color32::WHITE;
+
+/// Overview of the whole map for navigation
+#[derive(Default)]
+pub struct Minimap {
+    texture: Option<TextureHandle>,
+    /// Whether the texture was drawn from tile graphics rather than cell colors
+    texture_uses_tiles: bool,
+}
+
+impl Minimap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops the overview so it is redrawn, e.g. after opening a map or loading tilesets
+    pub fn invalidate(&mut self) {
+        self.texture = None;
+    }
+
+    /// Returns the overview texture, drawing it if needed
+    ///
+    /// Each cell becomes one pixel: tiles are scaled down to a single pixel,
+    /// which averages their colors, and cells without a tile use their cell
+    /// type color.
+    fn texture(&mut self, ui: &Ui, map: &Map, use_tiles: bool) -> &TextureHandle {
+        if self.texture_uses_tiles != use_tiles {
+            self.texture = None;
+        }
+        self.texture_uses_tiles = use_tiles;
+
+        self.texture.get_or_insert_with(|| {
+            let options = RenderOptions {
+                scale: 1.0 / Tileset::DEFAULT_TILE_SIZE as f32,
+                ..RenderOptions::default()
+            };
+            let image = if use_tiles {
+                render_map(map, &options)
+            } else {
+                // Without tilesets the renderer falls back to cell colors
+                let mut map = map.clone();
+                map.tileset_cache = None;
+                render_map(&map, &options)
+            };
+            ui.ctx().load_texture(
+                "minimap",
+                egui::ColorImage::from_rgba_unmultiplied(
+                    [image.width() as usize, image.height() as usize],
+                    image.as_flat_samples().as_slice(),
+                ),
+                TextureOptions::LINEAR,
+            )
+        })
+    }
+
+    /// Show the minimap, moving `view` when the minimap is clicked or dragged
+    pub fn show(&mut self, ui: &mut Ui, map: &Map, view: &mut MapView) {
+        let map_size = egui::vec2(map.info.width as f32, map.info.height as f32);
+        if map_size.x <= 0.0 || map_size.y <= 0.0 {
+            return;
+        }
+
+        // Fit the map's shape into the available width
+        let scale = (ui.available_width().min(MAX_SIZE) / map_size.x).min(MAX_SIZE / map_size.y);
+        let use_tiles = view.config().use_tilesets && map.tileset_cache.is_some();
+        let texture_id = self.texture(ui, map, use_tiles).id();
+
+        let (response, painter) = ui.allocate_painter(map_size * scale, Sense::click_and_drag());
+        let rect = response.rect;
+        painter.image(
+            texture_id,
+            rect,
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+            Color32::WHITE,
+        );
+
+        // Outline what the main view shows, clipped to the minimap
+        if let Some(visible) = view.visible_cells() {
+            let to_minimap = |cell: Pos2| rect.min + cell.to_vec2() * scale;
+            let outline = Rect::from_min_max(to_minimap(visible.min), to_minimap(visible.max));
+            painter.with_clip_rect(rect).rect_stroke(
+                outline,
+                0.0,
+                Stroke::new(1.5, VIEWPORT_COLOR),
+            );
+        }
+
+        if response.clicked() || response.dragged() {
+            if let Some(pointer) = response.interact_pointer_pos() {
+                let cell = ((pointer - rect.min) / scale).to_pos2();
+                view.center_on(cell.clamp(Pos2::ZERO, map_size.to_pos2()));
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+//! Overlay toggles and legend for OP2MapViewer
+//!
+//! Overlays tint cells with gameplay information such as passability or the
+//! microbe flag. The legend lists the colors of every enabled overlay.
+
+use eframe::egui::{self, Color32, Sense, Ui};
+
+use crate::render::Overlay;
+
+/// Size of a color swatch in the legend, in points
+const SWATCH_SIZE: f32 = 12.0;
+
+/// Shows a checkbox for each overlay and returns whether any changed
+pub fn overlay_checkboxes(ui: &mut Ui, overlays: &mut Vec<Overlay>) -> bool {
+    let mut changed = false;
+    for overlay in Overlay::ALL {
+        let mut enabled = overlays.contains(&overlay);
+        if ui.checkbox(&mut enabled, overlay.name()).changed() {
+            changed = true;
+            if enabled {
+                overlays.push(overlay);
+            } else {
+                overlays.retain(|&other| other != overlay);
+            }
+        }
+    }
+    changed
+}
+
+/// Shows the legend window for the enabled overlays, if any are enabled
+pub fn show_legend(ctx: &egui::Context, overlays: &[Overlay]) {
+    if overlays.is_empty() {
+        return;
+    }
+
+    egui::Window::new("Legend")
+        .anchor(egui::Align2::LEFT_BOTTOM, [8.0, -8.0])
+        .resizable(false)
+        .show(ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(ctx.screen_rect().height() * 0.5)
+                .show(ui, |ui| {
+                    for overlay in Overlay::ALL {
+                        if !overlays.contains(&overlay) {
+                            continue;
+                        }
+                        ui.strong(overlay.name());
+                        for ([r, g, b], label) in overlay.legend() {
+                            ui.horizontal(|ui| {
+                                swatch(ui, Color32::from_rgb(r, g, b));
+                                ui.label(label);
+                            });
+                        }
+                    }
+                });
+        });
+}
+
+fn swatch(ui: &mut Ui, color: Color32) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(SWATCH_SIZE, SWATCH_SIZE), Sense::hover());
+    ui.painter().rect_filled(rect, 2.0, color);
+    ui.painter()
+        .rect_stroke(rect, 2.0, egui::Stroke::new(1.0, Color32::GRAY));
+}
@@ -0,0 +1,216 @@
+//! Tile group browser for OP2MapViewer
+//!
+//! Tile groups are the multi-tile pieces, such as cliffs and craters, that a
+//! map lists after its tile mapping table. The browser lists them by name,
+//! previews the selected group from the tilesets and highlights the cells
+//! that use its tile mappings.
+
+use std::collections::HashSet;
+
+use eframe::egui::{self, TextureHandle, TextureOptions};
+
+use super::map_view::MapView;
+use crate::map::types::{Map, Position};
+use crate::map::Tileset;
+use crate::render::render_tile_group;
+
+/// Largest side of the rendered preview, in pixels
+const MAX_PREVIEW_PIXELS: u32 = 512;
+
+/// Largest side of the preview on screen, in points
+const MAX_PREVIEW_SIZE: f32 = 256.0;
+
+/// The selected group's preview and where its mappings are used
+struct Selection {
+    group: usize,
+    preview: TextureHandle,
+    mappings: HashSet<u16>,
+    /// Cells drawn with one of the group's mappings, in row order
+    uses: Vec<Position>,
+    /// Use shown by the last "Next Use" click
+    next_use: usize,
+}
+
+/// Window listing a map's tile groups
+pub struct TileGroupBrowser {
+    pub open: bool,
+    filter: String,
+    highlight: bool,
+    selection: Option<Selection>,
+    /// Whether the map view currently highlights the selection
+    highlighting: bool,
+}
+
+impl Default for TileGroupBrowser {
+    fn default() -> Self {
+        Self {
+            open: false,
+            filter: String::new(),
+            highlight: true,
+            selection: None,
+            highlighting: false,
+        }
+    }
+}
+
+impl TileGroupBrowser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forgets the selection, e.g. after opening another map or loading tilesets
+    pub fn reset(&mut self, view: &mut MapView) {
+        self.selection = None;
+        self.update_highlight(view);
+    }
+
+    /// Shows the browser window if it is open, and keeps the map view's
+    /// highlight in step with it
+    pub fn show(&mut self, ctx: &egui::Context, map: Option<&Map>, view: &mut MapView) {
+        if self.open {
+            let mut open = self.open;
+            egui::Window::new("Tile Groups")
+                .open(&mut open)
+                .default_width(420.0)
+                .show(ctx, |ui| match map {
+                    Some(map) if !map.tile_groups.is_empty() => self.show_contents(ui, map, view),
+                    Some(_) => {
+                        ui.label("This map has no tile groups");
+                    }
+                    None => {
+                        ui.label("Open a map to browse its tile groups");
+                    }
+                });
+            self.open = open;
+        }
+        self.update_highlight(view);
+    }
+
+    fn show_contents(&mut self, ui: &mut egui::Ui, map: &Map, view: &mut MapView) {
+        ui.horizontal(|ui| {
+            ui.label("Filter:");
+            ui.text_edit_singleline(&mut self.filter);
+        });
+        ui.separator();
+
+        let filter = self.filter.to_lowercase();
+        let selected = self.selection.as_ref().map(|selection| selection.group);
+        let mut clicked = None;
+        ui.horizontal_top(|ui| {
+            egui::ScrollArea::vertical()
+                .id_source("tile_group_list")
+                .max_width(160.0)
+                .max_height(MAX_PREVIEW_SIZE + 80.0)
+                .show(ui, |ui| {
+                    for (index, group) in map.tile_groups.iter().enumerate() {
+                        let name = group.display_name();
+                        if !name.to_lowercase().contains(&filter) {
+                            continue;
+                        }
+                        let label = if name.is_empty() {
+                            format!("#{}", index)
+                        } else {
+                            name
+                        };
+                        if ui
+                            .selectable_label(selected == Some(index), label)
+                            .clicked()
+                        {
+                            clicked = Some(index);
+                        }
+                    }
+                });
+
+            ui.separator();
+            ui.vertical(|ui| self.show_selection(ui, map, view));
+        });
+
+        if let Some(index) = clicked {
+            if selected != Some(index) {
+                self.select(ui.ctx(), map, index);
+            }
+        }
+    }
+
+    fn show_selection(&mut self, ui: &mut egui::Ui, map: &Map, view: &mut MapView) {
+        let Some(selection) = &mut self.selection else {
+            ui.label("Select a group to preview it");
+            return;
+        };
+        let Some(group) = map.tile_groups.get(selection.group) else {
+            return;
+        };
+
+        let size = selection.preview.size_vec2();
+        let scale = (MAX_PREVIEW_SIZE / size.x.max(size.y)).min(1.0);
+        ui.image((selection.preview.id(), size * scale));
+        if map.tileset_cache.is_none() {
+            ui.label("Load tilesets to see the tiles");
+        }
+
+        ui.label(format!("{}x{} cells", group.width, group.height));
+        ui.label(format!("{} distinct mappings", selection.mappings.len()));
+        ui.label(format!("Used by {} cells on the map", selection.uses.len()));
+        ui.checkbox(&mut self.highlight, "Highlight on map");
+        ui.add_enabled_ui(!selection.uses.is_empty(), |ui| {
+            if ui.button("Next Use").clicked() {
+                let position = selection.uses[selection.next_use % selection.uses.len()];
+                selection.next_use = (selection.next_use + 1) % selection.uses.len();
+                // Center on the middle of the cell
+                view.center_on(egui::pos2(position.x as f32 + 0.5, position.y as f32 + 0.5));
+            }
+        });
+    }
+
+    /// Selects a group, rendering its preview and finding where it is used
+    fn select(&mut self, ctx: &egui::Context, map: &Map, index: usize) {
+        let group = &map.tile_groups[index];
+        let tile_pixels = (MAX_PREVIEW_PIXELS / group.width.max(group.height).max(1))
+            .clamp(1, Tileset::DEFAULT_TILE_SIZE);
+        let image = render_tile_group(map, group, tile_pixels);
+        let preview = ctx.load_texture(
+            "tile_group_preview",
+            egui::ColorImage::from_rgba_unmultiplied(
+                [image.width() as usize, image.height() as usize],
+                image.as_flat_samples().as_slice(),
+            ),
+            TextureOptions::NEAREST,
+        );
+
+        let mappings: HashSet<u16> = group
+            .mapping_indices
+            .iter()
+            .filter_map(|&mapping| u16::try_from(mapping).ok())
+            .collect();
+        let uses = map
+            .cells()
+            .filter(|cell| mappings.contains(&cell.tile.tile_mapping_index()))
+            .map(|cell| cell.position)
+            .collect();
+
+        self.selection = Some(Selection {
+            group: index,
+            preview,
+            mappings,
+            uses,
+            next_use: 0,
+        });
+        // Show the new group's cells
+        self.highlighting = false;
+    }
+
+    /// Highlights the selected group's cells while the window is open
+    fn update_highlight(&mut self, view: &mut MapView) {
+        let wanted = self.open && self.highlight && self.selection.is_some();
+        if wanted == self.highlighting {
+            return;
+        }
+        match &self.selection {
+            Some(selection) if wanted => {
+                view.set_highlighted_mappings(selection.mappings.iter().copied())
+            }
+            _ => view.clear_highlight(),
+        }
+        self.highlighting = wanted;
+    }
+}
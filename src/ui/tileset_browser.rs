@@ -0,0 +1,238 @@
+//! Tileset browser for OP2MapViewer
+//!
+//! Lists the tilesets in the loaded [`TilesetCache`] and shows the tiles of
+//! one of them in a grid. Selecting a tile shows which of the map's tile
+//! mappings point at it and how many cells are drawn with it, which helps
+//! track down cells showing the wrong graphic.
+
+use eframe::egui::{self, Color32, Sense, Stroke, Ui};
+
+use super::map_view::TilesetTextures;
+use crate::map::types::Map;
+use crate::map::TilesetCache;
+
+/// Size of a tile in the grid, in points
+const TILE_SIZE: f32 = 40.0;
+
+/// Height of the index label under each tile, in points
+const LABEL_HEIGHT: f32 = 14.0;
+
+/// Outline of the selected tile
+const SELECTED_COLOR: Color32 = Color32::from_rgb(0, 220, 255);
+
+/// Where the selected tile is used in the current map
+struct TileUsage {
+    /// Tile mapping indices that draw the tile in any frame, in table order
+    mappings: Vec<usize>,
+    cells: usize,
+}
+
+/// Window for looking inside the loaded tilesets
+#[derive(Default)]
+pub struct TilesetBrowser {
+    pub open: bool,
+    selected_tileset: Option<String>,
+    /// Textures of the selected tileset
+    textures: Option<TilesetTextures>,
+    selected_tile: Option<u32>,
+    usage: Option<TileUsage>,
+}
+
+impl TilesetBrowser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops the textures and selection, e.g. after loading other tilesets
+    pub fn reset(&mut self) {
+        self.selected_tileset = None;
+        self.textures = None;
+        self.selected_tile = None;
+        self.usage = None;
+    }
+
+    /// Forgets where the selected tile is used, e.g. after opening another map
+    pub fn map_changed(&mut self) {
+        self.usage = None;
+    }
+
+    /// Shows the browser window if it is open
+    pub fn show(&mut self, ctx: &egui::Context, cache: Option<&TilesetCache>, map: Option<&Map>) {
+        let mut open = self.open;
+        egui::Window::new("Tilesets")
+            .open(&mut open)
+            .default_width(460.0)
+            .default_height(520.0)
+            .show(ctx, |ui| match cache {
+                Some(cache) => self.show_contents(ui, cache, map),
+                None => {
+                    ui.label("Load tilesets to browse them");
+                }
+            });
+        self.open = open;
+    }
+
+    fn show_contents(&mut self, ui: &mut Ui, cache: &TilesetCache, map: Option<&Map>) {
+        let tilesets = cache.tilesets();
+        egui::ScrollArea::vertical()
+            .id_source("tileset_list")
+            .max_height(140.0)
+            .show(ui, |ui| {
+                egui::Grid::new("tileset_table")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.strong("Name");
+                        ui.strong("Size");
+                        ui.strong("Tiles");
+                        ui.strong("Source");
+                        ui.end_row();
+
+                        for &(name, tileset) in &tilesets {
+                            let selected = self.selected_tileset.as_deref() == Some(name);
+                            if ui.selectable_label(selected, name).clicked() && !selected {
+                                self.select_tileset(ui.ctx(), cache, name);
+                            }
+                            let image = tileset.image();
+                            ui.label(format!("{}x{}", image.width(), image.height()));
+                            ui.label(tileset.tile_count().to_string());
+                            ui.label(tileset.source().unwrap_or("-"));
+                            ui.end_row();
+                        }
+                    });
+            });
+        ui.separator();
+
+        let Some(name) = self.selected_tileset.clone() else {
+            ui.label("Select a tileset to see its tiles");
+            return;
+        };
+        let Some(tileset) = cache.get_tileset(&name) else {
+            return;
+        };
+
+        if let Some(tile) = self.selected_tile {
+            self.show_tile_details(ui, &name, tile, map);
+            ui.separator();
+        }
+
+        // Lay the tiles out in as many columns as fit, drawing only visible rows
+        let Some(textures) = &self.textures else {
+            return;
+        };
+        let tile_count = tileset.tile_count();
+        let spacing = ui.spacing().item_spacing;
+        let columns = ((ui.available_width() + spacing.x) / (TILE_SIZE + spacing.x))
+            .floor()
+            .max(1.0) as u32;
+        let rows = tile_count.div_ceil(columns);
+        let mut clicked = None;
+        egui::ScrollArea::vertical()
+            .id_source("tileset_tiles")
+            .auto_shrink([false, false])
+            .show_rows(
+                ui,
+                TILE_SIZE + LABEL_HEIGHT,
+                rows as usize,
+                |ui, row_range| {
+                    for row in row_range {
+                        ui.horizontal(|ui| {
+                            let first = row as u32 * columns;
+                            for index in first..(first + columns).min(tile_count) {
+                                let selected = self.selected_tile == Some(index);
+                                if tile_button(ui, textures, index, selected).clicked() {
+                                    clicked = Some(index);
+                                }
+                            }
+                        });
+                    }
+                },
+            );
+
+        if let Some(index) = clicked {
+            self.selected_tile = Some(index);
+            self.usage = None;
+        }
+    }
+
+    fn show_tile_details(&mut self, ui: &mut Ui, tileset_name: &str, tile: u32, map: Option<&Map>) {
+        ui.strong(format!("{} tile {}", tileset_name, tile));
+        let Some(map) = map else {
+            ui.label("Open a map to see where the tile is used");
+            return;
+        };
+
+        let usage = self
+            .usage
+            .get_or_insert_with(|| tile_usage(map, tileset_name, tile));
+        if usage.mappings.is_empty() {
+            ui.label("No tile mapping uses this tile");
+            return;
+        }
+        let mappings: Vec<String> = usage
+            .mappings
+            .iter()
+            .map(|mapping| mapping.to_string())
+            .collect();
+        ui.label(format!("Tile mappings: {}", mappings.join(", ")));
+        ui.label(format!("Used by {} cells on the map", usage.cells));
+    }
+
+    fn select_tileset(&mut self, ctx: &egui::Context, cache: &TilesetCache, name: &str) {
+        self.reset();
+        if let Some(tileset) = cache.get_tileset(name) {
+            self.textures = Some(TilesetTextures::load(ctx, name, tileset));
+            self.selected_tileset = Some(name.to_string());
+        }
+    }
+}
+
+/// Draws one tile of the grid with its index under it
+fn tile_button(
+    ui: &mut Ui,
+    textures: &TilesetTextures,
+    index: u32,
+    selected: bool,
+) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(
+        egui::vec2(TILE_SIZE, TILE_SIZE + LABEL_HEIGHT),
+        Sense::click(),
+    );
+    let tile_rect = egui::Rect::from_min_size(rect.min, egui::Vec2::splat(TILE_SIZE));
+    let painter = ui.painter();
+    if let Some((texture_id, uv)) = textures.tile(index) {
+        painter.image(texture_id, tile_rect, uv, Color32::WHITE);
+    }
+    if selected {
+        painter.rect_stroke(tile_rect, 0.0, Stroke::new(2.0, SELECTED_COLOR));
+    } else if response.hovered() {
+        painter.rect_stroke(tile_rect, 0.0, Stroke::new(1.0, Color32::GRAY));
+    }
+    painter.text(
+        egui::pos2(rect.center().x, tile_rect.max.y),
+        egui::Align2::CENTER_TOP,
+        index.to_string(),
+        egui::FontId::proportional(10.0),
+        ui.visuals().text_color(),
+    );
+    response.on_hover_text(format!("Tile {}", index))
+}
+
+/// Finds the map's tile mappings that draw a tile, in any animation frame,
+/// and counts the cells using them
+fn tile_usage(map: &Map, tileset_name: &str, tile: u32) -> TileUsage {
+    let mappings: Vec<usize> = map
+        .tile_mappings
+        .iter()
+        .enumerate()
+        .filter(|(_, mapping)| {
+            mapping.graphics().any(|graphic| graphic == tile)
+                && map.tileset_name(mapping.tileset_index) == Some(tileset_name)
+        })
+        .map(|(index, _)| index)
+        .collect();
+    let cells = map
+        .cells()
+        .filter(|cell| mappings.contains(&(cell.tile.tile_mapping_index() as usize)))
+        .count();
+    TileUsage { mappings, cells }
+}